@@ -1,9 +1,9 @@
 use candid::{CandidType, Deserialize, Principal};
 use ic_cdk::api::time;
-use ic_cdk::{query, update};
+use ic_cdk::{init, post_upgrade, query, update};
 use std::cell::RefCell;
-use std::collections::HashMap;
-use std::collections::BTreeMap;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::Duration;
 
 
 // Data structures
@@ -17,7 +17,23 @@ pub struct Todo {
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub enum PostType {
     Original,
-    Reshare { original_post_id: u64, original_author: Principal }
+    Reshare { original_post_id: u64, original_author: Principal },
+    Poll { poll_id: u64 },
+    // A post embedding someone's comment; `post_id` is the post the comment was left on
+    CommentQuote { comment_id: u64, comment_author: Principal, post_id: u64 },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum Visibility {
+    Public,
+    CloseFriends,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct MediaAttachment {
+    pub url: String,
+    pub mime_type: String,
+    pub alt_text: Option<String>,
 }
 
 #[derive(CandidType, Deserialize, Clone, Debug)]
@@ -29,8 +45,18 @@ pub struct Post {
     pub likes: Vec<Principal>,
     pub comments: Vec<u64>,
     pub hashtags: Vec<String>,
+    pub mentions: Vec<Principal>,
     pub post_type: PostType,
     pub reshare_count: u64,
+    pub media: Vec<MediaAttachment>,
+    pub visibility: Visibility,
+    pub content_warning: Option<String>,
+    // Set by the link-spam heuristic; flagged posts stay visible but rank lower
+    pub flagged: bool,
+    // Turned off by the author to stop new comments; existing ones stay visible
+    pub comments_enabled: bool,
+    // Set once enough distinct users report the post; only its author still sees it
+    pub hidden_by_reports: bool,
 }
 
 #[derive(CandidType, Deserialize, Clone, Debug)]
@@ -40,6 +66,67 @@ pub struct Comment {
     pub author: Principal,
     pub content: String,
     pub created_at: u64,
+    pub likes: Vec<Principal>,
+    pub parent_id: Option<u64>,
+    // Direct reply ids, so reply counts and threads don't need a scan over all comments
+    pub replies: Vec<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ThreadedComment {
+    pub comment: Comment,
+    pub depth: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum CommentSort {
+    Newest,
+    Oldest,
+    Top,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct PostDetail {
+    pub post: Post,
+    pub comments: Vec<Comment>,
+    pub author_profile: Option<UserProfile>,
+    pub like_count: u64,
+    pub comment_count: u64,
+    pub reshare_count: u64,
+    pub reactions: ReactionSummary,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct PostAnalytics {
+    pub post_id: u64,
+    pub like_count: u64,
+    pub comment_count: u64,
+    pub reshare_count: u64,
+    pub impression_count: u64,
+    pub reactions: Vec<(String, u64)>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum EngagementKind {
+    Like,
+    Comment { comment_id: u64 },
+    Reshare { reshare_post_id: u64 },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct EngagementEvent {
+    pub kind: EngagementKind,
+    pub user_id: Principal,
+    pub timestamp: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ReactionSummary {
+    pub post_id: u64,
+    // (reaction, count), most used first
+    pub counts: Vec<(String, u64)>,
+    pub total: u64,
+    pub caller_reaction: Option<String>,
 }
 
 #[derive(CandidType, Deserialize, Clone, Debug)]
@@ -51,6 +138,24 @@ pub struct UserProfile {
     pub followers_count: u64,
     pub following_count: u64,
     pub created_at: u64,
+    pub active: bool,
+    pub verified: bool,
+    pub username_history: Vec<String>,
+    pub feed_preference: FeedAlgorithm,
+    // Website/social buttons shown under the bio; set with set_profile_links
+    pub links: Vec<ProfileLink>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ProfileLink {
+    pub label: String,
+    pub url: String,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum FeedAlgorithm {
+    Chronological,
+    Personalized,
 }
 
 #[derive(CandidType, Deserialize, Clone, Debug)]
@@ -58,9 +163,26 @@ pub enum NotificationType {
     Follow { user_id: Principal },
     Like { post_id: u64, user_id: Principal },
     Comment { post_id: u64, user_id: Principal, comment_id: u64 },
-    Message { user_id: Principal, message_id: u64 },
+    Message { user_id: Principal, message_id: u64, has_attachments: bool },
     Mention { post_id: u64, user_id: Principal },
     Reshare { post_id: u64, user_id: Principal },
+    Reaction { post_id: u64, user_id: Principal, reaction: String },
+    CommentLike { post_id: u64, comment_id: u64, user_id: Principal },
+    // `post_id` is the new post that quotes the recipient's comment
+    CommentQuote { post_id: u64, comment_id: u64, user_id: Principal },
+    // Sent to the parent comment's author; `comment_id` is the reply itself
+    Reply { post_id: u64, comment_id: u64, user_id: Principal },
+}
+
+// Which kinds of notification a user wants to receive; reactions and comment likes count as likes, replies as comments
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct NotificationPreferences {
+    pub follows: bool,
+    pub likes: bool,
+    pub comments: bool,
+    pub mentions: bool,
+    pub reshares: bool,
+    pub messages: bool,
 }
 
 #[derive(CandidType, Deserialize, Clone, Debug)]
@@ -72,6 +194,18 @@ pub struct Notification {
     pub read: bool,
 }
 
+// Several raw notifications of the same kind about the same post, collapsed into one row
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct GroupedNotification {
+    pub kind: String,
+    pub post_id: Option<u64>,
+    pub count: u64,
+    pub recent_actors: Vec<Principal>,
+    pub notification_ids: Vec<u64>,
+    pub latest_at: u64,
+    pub read: bool,
+}
+
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct Message {
     pub id: u64,
@@ -79,24 +213,232 @@ pub struct Message {
     pub to: Principal,
     pub content: String,
     pub created_at: u64,
+    // Kept alongside `status` for older clients; true exactly when status is Read
     pub read: bool,
+    pub thread_id: String,
+    pub status: MessageStatus,
+    pub attachments: Vec<MediaAttachment>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum MessageStatus {
+    Sent,
+    Delivered,
+    Read,
 }
 
+// Incremental chat poll result; pass `server_time` back as `since` on the next call
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct MessageSync {
+    pub messages: Vec<Message>,
+    pub server_time: u64,
+}
+
+// Group threads have a name; in their messages `to` is the anonymous principal since there is no single recipient
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct ChatThread {
     pub id: String,
     pub participants: Vec<Principal>,
     pub last_message: Option<Message>,
     pub updated_at: u64,
+    pub name: Option<String>,
+    pub is_group: bool,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum ThreadFilter {
+    All,
+    Archived,
+    Pinned,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ChatThreadSummary {
+    pub thread: ChatThread,
+    pub other_participant: Principal,
+    pub unread_count: u64,
+}
+
+// Edge in the interaction graph; weight decays with time since last_interaction
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct InteractionEdge {
+    pub weight: u64,
+    pub last_interaction: u64,
+}
+
+// For group rows `other_username` carries the group name and `other_participant` is the anonymous principal
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ConversationPreview {
+    pub thread_id: String,
+    pub is_group: bool,
+    pub other_participant: Principal,
+    pub other_username: Option<String>,
+    pub last_message_preview: Option<String>,
+    pub last_message_from: Option<Principal>,
+    pub updated_at: u64,
 }
 
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct TrendingTopic {
     pub hashtag: String,
     pub count: u64,
+    // Ranking uses this rather than `count`, so one account repeating a tag can't make it trend
+    pub distinct_authors: u64,
     pub last_used: u64,
 }
 
+// Breakdown of a post's personalized-feed score for one viewer
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct RankExplanation {
+    pub post_id: u64,
+    pub follow_bonus: u64,
+    pub verified_bonus: u64,
+    pub content_affinity: u64,
+    pub interaction_strength: u64,
+    pub hidden_topic_penalty: u64,
+    // Flagged posts have their score divided by FLAGGED_SCORE_DIVISOR
+    pub flagged: bool,
+    pub total: u64,
+}
+
+// Header data for a hashtag results page
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct HashtagStats {
+    pub hashtag: String,
+    pub post_count: u64,
+    // Likes + comments + reshares across the tag's posts
+    pub total_engagement: u64,
+    pub first_seen: u64,
+    pub last_used: u64,
+    pub trending: bool,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum ActivityAction {
+    LikedPost { post_id: u64 },
+    Commented { post_id: u64, comment_id: u64 },
+    Followed { user_id: Principal },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ActivityItem {
+    pub id: u64,
+    pub actor: Principal,
+    pub action: ActivityAction,
+    pub created_at: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ScheduledPost {
+    pub id: u64,
+    pub author: Principal,
+    pub content: String,
+    pub publish_at: u64,
+    pub created_at: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Draft {
+    pub id: u64,
+    pub content: String,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+// Poll structures
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Poll {
+    pub id: u64,
+    pub post_id: u64,
+    pub question: String,
+    pub options: Vec<String>,
+    pub votes: Vec<Vec<Principal>>,
+    pub created_at: u64,
+    pub expires_at: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct PollResults {
+    pub poll_id: u64,
+    pub post_id: u64,
+    pub question: String,
+    pub options: Vec<String>,
+    pub vote_counts: Vec<u64>,
+    pub total_votes: u64,
+    pub expires_at: u64,
+    pub closed: bool,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct PlatformStats {
+    pub total_users: u64,
+    pub total_posts: u64,
+    pub total_comments: u64,
+    pub total_messages: u64,
+    pub active_trending_topics: u64,
+    pub total_tokens_in_circulation: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum Relationship {
+    None,
+    Following,
+    Follower,
+    Mutual,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SessionInfo {
+    pub principal: Principal,
+    pub profile: Option<UserProfile>,
+    pub needs_profile: bool,
+    pub unread_notifications: u64,
+    pub unread_messages: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ProfileStats {
+    pub user_id: Principal,
+    pub post_count: u64,
+    pub likes_received: u64,
+    pub comments_received: u64,
+    pub reshares_received: u64,
+    pub followers_count: u64,
+    pub following_count: u64,
+}
+
+// `missing` lists the unmet checks by key: "bio", "avatar", "followers", "following", "post"
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ProfileCompleteness {
+    pub score: u8,
+    pub missing: Vec<String>,
+}
+
+// Everything a user created or received, for data portability requests
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct UserDataExport {
+    pub user_id: Principal,
+    pub exported_at: u64,
+    pub profile: Option<UserProfile>,
+    pub posts: Vec<Post>,
+    pub comments: Vec<Comment>,
+    pub messages: Vec<Message>,
+    pub following: Vec<Principal>,
+    pub followers: Vec<Principal>,
+    pub transactions: Vec<Transaction>,
+}
+
+// Moderation structures
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Report {
+    pub id: u64,
+    pub reporter: Principal,
+    pub post_id: u64,
+    pub reason: String,
+    pub created_at: u64,
+    pub resolved: bool,
+}
+
 // Wallet structures
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct Wallet {
@@ -118,7 +460,7 @@ pub struct Transaction {
     pub memo: Option<String>,
 }
 
-#[derive(CandidType, Deserialize, Clone, Debug)]
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
 pub enum TransactionType {
     Transfer,
     Tip,
@@ -133,6 +475,43 @@ pub enum TransactionStatus {
     Failed,
 }
 
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum EscrowStatus {
+    Held,
+    Released,
+    Refunded,
+}
+
+// Buyer funds locked for a purchase until the buyer releases them or the seller refunds
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Escrow {
+    pub id: u64,
+    pub buyer: Principal,
+    pub seller: Principal,
+    pub amount: u64,
+    pub status: EscrowStatus,
+    pub created_at: u64,
+    pub resolved_at: Option<u64>,
+    // The Purchase recorded when the funds were locked; Pending while held
+    pub transaction_id: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum ContentFilterMode {
+    Off,
+    Reject,
+    // Filtered words are replaced with asterisks of the same length
+    Mask,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TokenMetadata {
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u8,
+    pub total_supply: u64,
+}
+
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct TransferRequest {
     pub to: Principal,
@@ -149,29 +528,227 @@ pub enum Result<T, E> {
 
 // Thread-local storage
 thread_local! {
-    static TODOS: RefCell<Vec<Todo>> = RefCell::new(Vec::new());
+    static TODOS: RefCell<Vec<Todo>> = const { RefCell::new(Vec::new()) };
     static POSTS: RefCell<HashMap<u64, Post>> = RefCell::new(HashMap::new());
     static COMMENTS: RefCell<HashMap<u64, Comment>> = RefCell::new(HashMap::new());
     static PROFILES: RefCell<HashMap<Principal, UserProfile>> = RefCell::new(HashMap::new());
+    // Lowercased username -> owner, for uniqueness checks
+    static USERNAMES: RefCell<HashMap<String, Principal>> = RefCell::new(HashMap::new());
+    static LAST_USERNAME_CHANGE: RefCell<HashMap<Principal, u64>> = RefCell::new(HashMap::new());
     static FOLLOWS: RefCell<HashMap<Principal, Vec<Principal>>> = RefCell::new(HashMap::new());
+    // Timestamps of each user's most recent follows/comments/messages, for rate limiting
+    static RECENT_FOLLOWS: RefCell<HashMap<Principal, VecDeque<u64>>> = RefCell::new(HashMap::new());
+    static RECENT_COMMENTS: RefCell<HashMap<Principal, VecDeque<u64>>> = RefCell::new(HashMap::new());
+    static RECENT_MESSAGES: RefCell<HashMap<Principal, VecDeque<u64>>> = RefCell::new(HashMap::new());
+    static BLOCKS: RefCell<HashMap<Principal, Vec<Principal>>> = RefCell::new(HashMap::new());
     static NOTIFICATIONS: RefCell<HashMap<u64, Notification>> = RefCell::new(HashMap::new());
+    // Only users who changed something have an entry; everyone else gets the defaults
+    static NOTIFICATION_PREFERENCES: RefCell<HashMap<Principal, NotificationPreferences>> = RefCell::new(HashMap::new());
     static MESSAGES: RefCell<HashMap<u64, Message>> = RefCell::new(HashMap::new());
     static CHAT_THREADS: RefCell<HashMap<String, ChatThread>> = RefCell::new(HashMap::new());
+    // Per-user inbox state; thread ids are only meaningful to participants
+    static ARCHIVED_THREADS: RefCell<HashMap<Principal, HashSet<String>>> = RefCell::new(HashMap::new());
+    static PINNED_THREADS: RefCell<HashMap<Principal, HashSet<String>>> = RefCell::new(HashMap::new());
+    // Group messages have no single recipient, so each member's progress is kept as the newest message id they've read
+    static GROUP_READ_UP_TO: RefCell<HashMap<String, HashMap<Principal, u64>>> = RefCell::new(HashMap::new());
     static TRENDING_TOPICS: RefCell<HashMap<String, TrendingTopic>> = RefCell::new(HashMap::new());
-    static COUNTER: RefCell<u64> = RefCell::new(0);
-    static POST_COUNTER: RefCell<u64> = RefCell::new(0);
-    static COMMENT_COUNTER: RefCell<u64> = RefCell::new(0);
-    static NOTIFICATION_COUNTER: RefCell<u64> = RefCell::new(0);
-    static MESSAGE_COUNTER: RefCell<u64> = RefCell::new(0);
-    static INTERACTION_GRAPH: RefCell<HashMap<Principal, HashMap<Principal, u64>>> = RefCell::new(HashMap::new());
+    static TOPIC_AUTHORS: RefCell<HashMap<String, HashSet<Principal>>> = RefCell::new(HashMap::new());
+    // Hashtag -> (timestamp, author) of its most recent uses, oldest first, capped at MAX_TOPIC_USAGE_SAMPLES
+    static TOPIC_USAGE: RefCell<HashMap<String, VecDeque<(u64, Principal)>>> = RefCell::new(HashMap::new());
+    // Hashtag -> other hashtag -> number of posts carrying both; kept symmetric
+    static HASHTAG_COOCCURRENCE: RefCell<HashMap<String, HashMap<String, u64>>> = RefCell::new(HashMap::new());
+    // Lowercased alias -> canonical tag (e.g. "#js" -> "#javascript"); targets are never aliases themselves
+    static HASHTAG_ALIASES: RefCell<HashMap<String, String>> = RefCell::new(HashMap::new());
+    static COUNTER: RefCell<u64> = const { RefCell::new(0) };
+    static POST_COUNTER: RefCell<u64> = const { RefCell::new(0) };
+    static COMMENT_COUNTER: RefCell<u64> = const { RefCell::new(0) };
+    static NOTIFICATION_COUNTER: RefCell<u64> = const { RefCell::new(0) };
+    static MESSAGE_COUNTER: RefCell<u64> = const { RefCell::new(0) };
+    static GROUP_CHAT_COUNTER: RefCell<u64> = const { RefCell::new(0) };
+    static INTERACTION_GRAPH: RefCell<HashMap<Principal, HashMap<Principal, InteractionEdge>>> = RefCell::new(HashMap::new());
+    // Post id -> reactor -> reaction; one reaction per user per post
+    static POST_REACTIONS: RefCell<HashMap<u64, HashMap<Principal, String>>> = RefCell::new(HashMap::new());
     static CONTENT_AFFINITY: RefCell<HashMap<Principal, HashMap<String, u64>>> = RefCell::new(HashMap::new());
+    static MUTED_HASHTAGS: RefCell<HashMap<Principal, Vec<String>>> = RefCell::new(HashMap::new());
+    static HIDDEN_POSTS: RefCell<HashMap<Principal, HashSet<u64>>> = RefCell::new(HashMap::new());
+    // Posts the client reported as already scrolled past, oldest first
+    static SEEN_POSTS: RefCell<HashMap<Principal, VecDeque<u64>>> = RefCell::new(HashMap::new());
+    // Hashtag -> number of hidden posts that carried it, per user
+    static HIDDEN_TOPICS: RefCell<HashMap<Principal, HashMap<String, u64>>> = RefCell::new(HashMap::new());
+    static CLOSE_FRIENDS: RefCell<HashMap<Principal, Vec<Principal>>> = RefCell::new(HashMap::new());
+    // Author -> ids of every comment and reply they wrote
+    static COMMENTS_BY_USER: RefCell<HashMap<Principal, Vec<u64>>> = RefCell::new(HashMap::new());
+    // Mentioned user -> ids of posts that mention them
+    static MENTIONS_INDEX: RefCell<HashMap<Principal, Vec<u64>>> = RefCell::new(HashMap::new());
     
     // Wallet storage
     static WALLETS: RefCell<HashMap<Principal, Wallet>> = RefCell::new(HashMap::new());
     static TRANSACTIONS: RefCell<HashMap<u64, Transaction>> = RefCell::new(HashMap::new());
-    static TRANSACTION_COUNTER: RefCell<u64> = RefCell::new(0);
+    static TRANSACTION_COUNTER: RefCell<u64> = const { RefCell::new(0) };
+    static ESCROWS: RefCell<HashMap<u64, Escrow>> = RefCell::new(HashMap::new());
+    static ESCROW_COUNTER: RefCell<u64> = const { RefCell::new(0) };
+    // Sum of every balance ever credited from nothing (signup grants, test top-ups, mints)
+    static TOTAL_SUPPLY: RefCell<u64> = const { RefCell::new(0) };
+    static LAST_REWARD_CLAIM: RefCell<HashMap<Principal, u64>> = RefCell::new(HashMap::new());
+
+    // Chat presence storage
+    static TYPING: RefCell<HashMap<String, HashMap<Principal, u64>>> = RefCell::new(HashMap::new());
+    static LAST_SEEN: RefCell<HashMap<Principal, u64>> = RefCell::new(HashMap::new());
+    // Time of each user's most recent write (post, comment, like, follow, reaction, message)
+    static LAST_ACTIVE: RefCell<HashMap<Principal, u64>> = RefCell::new(HashMap::new());
+    // Users who turned read receipts off; everyone else shares them
+    static READ_RECEIPTS_DISABLED: RefCell<HashSet<Principal>> = RefCell::new(HashSet::new());
+
+    // Post analytics storage
+    static POST_IMPRESSIONS: RefCell<HashMap<u64, u64>> = RefCell::new(HashMap::new());
+    // Post id -> when each current liker liked it; Post.likes stays the source of truth for who
+    static LIKED_AT: RefCell<HashMap<u64, HashMap<Principal, u64>>> = RefCell::new(HashMap::new());
+
+    // Profile analytics storage
+    static PROFILE_VIEWS: RefCell<HashMap<Principal, u64>> = RefCell::new(HashMap::new());
+    static LAST_PROFILE_VIEW: RefCell<HashMap<(Principal, Principal), u64>> = RefCell::new(HashMap::new());
+
+    // Activity storage
+    // Newest last; trimmed from the front once it holds MAX_ACTIVITY_LOG entries
+    static ACTIVITY_LOG: RefCell<VecDeque<ActivityItem>> = const { RefCell::new(VecDeque::new()) };
+    static ACTIVITY_COUNTER: RefCell<u64> = const { RefCell::new(0) };
+
+    // Scheduled post storage
+    static SCHEDULED_POSTS: RefCell<HashMap<u64, ScheduledPost>> = RefCell::new(HashMap::new());
+    static SCHEDULED_POST_COUNTER: RefCell<u64> = const { RefCell::new(0) };
+
+    // Draft storage
+    static DRAFTS: RefCell<HashMap<Principal, Vec<Draft>>> = RefCell::new(HashMap::new());
+    static DRAFT_COUNTER: RefCell<u64> = const { RefCell::new(0) };
+
+    // Poll storage
+    static POLLS: RefCell<HashMap<u64, Poll>> = RefCell::new(HashMap::new());
+    static POLL_COUNTER: RefCell<u64> = const { RefCell::new(0) };
+
+    // Moderation storage
+    static REPORTS: RefCell<HashMap<u64, Report>> = RefCell::new(HashMap::new());
+    static REPORT_COUNTER: RefCell<u64> = const { RefCell::new(0) };
+    // Most posts one author may take in a page of the personalized feed
+    static FEED_AUTHOR_CAP: RefCell<usize> = const { RefCell::new(DEFAULT_FEED_AUTHOR_CAP) };
+    // Live post limit; admins change it with set_max_post_length, up to POST_LENGTH_CEILING
+    static MAX_POST_LENGTH: RefCell<usize> = const { RefCell::new(DEFAULT_MAX_POST_LENGTH) };
+    // Lowercased filtered words, applied to posts, comments and messages according to CONTENT_FILTER_MODE
+    static CONTENT_FILTER_WORDS: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
+    static CONTENT_FILTER_MODE: RefCell<ContentFilterMode> = const { RefCell::new(ContentFilterMode::Off) };
+    // Distinct unresolved reports that hide a post; adjustable by admins
+    static REPORT_HIDE_THRESHOLD: RefCell<u64> = const { RefCell::new(DEFAULT_REPORT_HIDE_THRESHOLD) };
+
+    // Principals allowed to call privileged endpoints (moderation, verification, minting)
+    static ADMINS: RefCell<Vec<Principal>> = const { RefCell::new(Vec::new()) };
 }
 
+// String identifiers accepted by get_notifications_filtered, one per NotificationType variant
+const NOTIFICATION_KINDS: [&str; 10] = ["Follow", "Like", "Comment", "Message", "Mention", "Reshare", "Reaction", "CommentLike", "CommentQuote", "Reply"];
+
+const MAX_POST_MEDIA: usize = 4;
+const MAX_MESSAGE_ATTACHMENTS: usize = 5;
+// How long after sending a message its sender may still unsend it
+const UNSEND_WINDOW_NS: u64 = 2 * 60 * 1_000_000_000;
+// Reactions are short emoji/shortcodes; some emoji span several scalar values
+const MAX_REACTION_LENGTH: usize = 16;
+const MAX_RESHARE_CHAIN_LENGTH: usize = 50;
+// Ids beyond this in a single follow_many/unfollow_many call are ignored
+const MAX_BULK_FOLLOW: usize = 100;
+
+// Content limits, counted in Unicode scalar values so multibyte emoji count as one character
+const DEFAULT_MAX_POST_LENGTH: usize = 5000;
+const POST_LENGTH_CEILING: usize = 50_000;
+const MAX_COMMENT_LENGTH: usize = 2000;
+const MAX_MESSAGE_LENGTH: usize = 2000;
+const MAX_CONTENT_WARNING_LENGTH: usize = 100;
+const MAX_PROFILE_LINKS: usize = 5;
+const MAX_LINK_LABEL_LENGTH: usize = 30;
+const MAX_LINK_URL_LENGTH: usize = 200;
+
+// Link-spam heuristic: flag posts with many links, or where links make up most of the words
+const MAX_LINKS_BEFORE_FLAG: usize = 5;
+const MIN_LINKS_FOR_RATIO_FLAG: usize = 2;
+// Personalized-feed scores of flagged posts are divided by this
+const FLAGGED_SCORE_DIVISOR: u64 = 4;
+const DEFAULT_FEED_AUTHOR_CAP: usize = 2;
+const DEFAULT_REPORT_HIDE_THRESHOLD: u64 = 5;
+const MESSAGE_PREVIEW_LENGTH: usize = 100;
+const MAX_GROUP_PARTICIPANTS: usize = 50;
+const MAX_DRAFTS_PER_USER: usize = 50;
+// Replies deeper than this are counted on their parent but not expanded in get_comment_thread
+const MAX_THREAD_DEPTH: u64 = 5;
+
+// Likes/reactions on the same post within this window collapse into one group (24 hours)
+const NOTIFICATION_GROUP_WINDOW_NS: u64 = 24 * 60 * 60 * 1_000_000_000;
+const MAX_GROUP_ACTORS: usize = 3;
+
+// Repeat views of the same profile by the same viewer inside this window count once (10 minutes)
+const PROFILE_VIEW_DEDUP_WINDOW_NS: u64 = 10 * 60 * 1_000_000_000;
+
+// Typing indicators older than this are treated as stale (10 seconds)
+const TYPING_TIMEOUT_NS: u64 = 10 * 1_000_000_000;
+
+const MIN_POLL_OPTIONS: usize = 2;
+const MAX_POLL_OPTIONS: usize = 6;
+
+// Extra personalized-feed score for posts by verified authors
+const VERIFIED_AUTHOR_BOOST: u64 = 3;
+// Personalized-feed penalty per hidden post sharing a hashtag with the candidate
+const HIDDEN_TOPIC_PENALTY: u64 = 5;
+
+// Interaction weights halve for every elapsed half-life (14 days)
+const INTERACTION_HALF_LIFE_NS: u64 = 14 * 24 * 60 * 60 * 1_000_000_000;
+// Only activity from the last week counts towards get_active_users, fading linearly with age
+const ACTIVE_USERS_WINDOW_NS: u64 = 7 * 24 * 60 * 60 * 1_000_000_000;
+const ACTIVE_POST_WEIGHT: u64 = 3;
+const ACTIVE_COMMENT_WEIGHT: u64 = 2;
+const ACTIVE_LIKE_WEIGHT: u64 = 1;
+
+// A trending topic counts as active if it was used within this window (24 hours)
+const ACTIVE_TOPIC_WINDOW_NS: u64 = 24 * 60 * 60 * 1_000_000_000;
+// A hashtag is "trending" if it ranks this high by uses within ACTIVE_TOPIC_WINDOW_NS
+const TRENDING_HASHTAG_RANK: u64 = 10;
+const ORPHANED_NOTIFICATION_SWEEP_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+// Per-topic usage history kept for windowed trends; hotter topics saturate at this count
+const MAX_TOPIC_USAGE_SAMPLES: usize = 1000;
+// Social thresholds for a profile to count as complete
+const PROFILE_MIN_FOLLOWERS: u64 = 1;
+const PROFILE_MIN_FOLLOWING: u64 = 1;
+// Per followee of the caller who follows a suggested user
+const SECOND_DEGREE_WEIGHT: u64 = 15;
+// A topic's trending score halves once it has gone this long without being used
+const TRENDING_DECAY_NS: u64 = 24 * 60 * 60 * 1_000_000_000;
+const TRENDING_SCORE_SCALE: u64 = 1000;
+// Oldest seen post ids are forgotten first, so they can resurface in the feed
+const MAX_SEEN_POSTS: usize = 500;
+// Following-activity only looks back this many likes, comments and follows across all users
+const MAX_ACTIVITY_LOG: usize = 10_000;
+
+// Rate limits: at most this many actions per rolling window
+const MAX_FOLLOWS_PER_WINDOW: usize = 20;
+const FOLLOW_RATE_WINDOW_NS: u64 = 60 * 60 * 1_000_000_000;
+const MAX_COMMENTS_PER_WINDOW: usize = 10;
+const COMMENT_RATE_WINDOW_NS: u64 = 60 * 1_000_000_000;
+const MAX_MESSAGES_PER_WINDOW: usize = 30;
+const MESSAGE_RATE_WINDOW_NS: u64 = 60 * 1_000_000_000;
+
+const MIN_USERNAME_LENGTH: usize = 3;
+const MAX_USERNAME_LENGTH: usize = 30;
+// Minimum time between username changes (30 days)
+const USERNAME_CHANGE_COOLDOWN_NS: u64 = 30 * 24 * 60 * 60 * 1_000_000_000;
+
+// Only posts from the last 48 hours are considered for trending posts
+const TRENDING_POSTS_WINDOW_NS: u64 = 48 * 60 * 60 * 1_000_000_000;
+
+// Token identity; all amounts are in base units, so 1 TTK = 10^TOKEN_DECIMALS units
+const TOKEN_NAME: &str = "ToknTalk Token";
+const TOKEN_SYMBOL: &str = "TTK";
+const TOKEN_DECIMALS: u8 = 8;
+const INITIAL_WALLET_BALANCE: u64 = 1000 * 10u64.pow(TOKEN_DECIMALS as u32);
+const DAILY_REWARD_AMOUNT: u64 = 10 * 10u64.pow(TOKEN_DECIMALS as u32);
+// Minimum time between daily reward claims (24 hours)
+const DAILY_REWARD_INTERVAL_NS: u64 = 24 * 60 * 60 * 1_000_000_000;
+
 // Helper functions
 fn get_next_id(counter: &'static std::thread::LocalKey<std::cell::RefCell<u64>>) -> u64 {
     counter.with(|c| {
@@ -181,11 +758,62 @@ fn get_next_id(counter: &'static std::thread::LocalKey<std::cell::RefCell<u64>>)
     })
 }
 
+fn decayed_weight(edge: &InteractionEdge, now: u64) -> u64 {
+    let half_lives = now.saturating_sub(edge.last_interaction) / INTERACTION_HALF_LIFE_NS;
+    if half_lives >= 64 {
+        0
+    } else {
+        edge.weight >> half_lives
+    }
+}
+
+fn mark_active(user: Principal) {
+    LAST_ACTIVE.with(|last_active| {
+        last_active.borrow_mut().insert(user, time());
+    });
+}
+
 fn update_interaction_graph(from_user: Principal, to_user: Principal, weight: u64) {
+    mark_active(from_user);
+    // Interacting with your own content says nothing about your connections
+    if from_user == to_user {
+        return;
+    }
+
+    let now = time();
     INTERACTION_GRAPH.with(|graph| {
         let mut graph = graph.borrow_mut();
         let user_interactions = graph.entry(from_user).or_insert_with(HashMap::new);
-        *user_interactions.entry(to_user).or_insert(0) += weight;
+        let edge = user_interactions.entry(to_user).or_insert(InteractionEdge { weight: 0, last_interaction: now });
+        // Fold the decay accrued so far into the stored weight before adding the new interaction
+        edge.weight = decayed_weight(edge, now).saturating_add(weight);
+        edge.last_interaction = now;
+    });
+}
+
+fn interaction_strength(from_user: Principal, to_user: Principal, now: u64) -> u64 {
+    INTERACTION_GRAPH.with(|graph| {
+        graph.borrow()
+            .get(&from_user)
+            .and_then(|interactions| interactions.get(&to_user))
+            .map_or(0, |edge| decayed_weight(edge, now))
+    })
+}
+
+fn record_activity(actor: Principal, action: ActivityAction) {
+    mark_active(actor);
+    let activity = ActivityItem {
+        id: get_next_id(&ACTIVITY_COUNTER),
+        actor,
+        action,
+        created_at: time(),
+    };
+    ACTIVITY_LOG.with(|log| {
+        let mut log = log.borrow_mut();
+        if log.len() >= MAX_ACTIVITY_LOG {
+            log.pop_front();
+        }
+        log.push_back(activity);
     });
 }
 
@@ -199,63 +827,725 @@ fn update_content_affinity(user: Principal, hashtags: &Vec<String>, weight: u64)
     });
 }
 
-fn update_trending_topics(hashtags: &Vec<String>) {
+// Negative counterpart of update_content_affinity; tags that drop to zero are forgotten
+fn reduce_content_affinity(user: Principal, hashtags: &[String], weight: u64) {
+    CONTENT_AFFINITY.with(|affinity| {
+        if let Some(user_affinity) = affinity.borrow_mut().get_mut(&user) {
+            for hashtag in hashtags {
+                if let Some(score) = user_affinity.get_mut(hashtag) {
+                    *score = score.saturating_sub(weight);
+                    if *score == 0 {
+                        user_affinity.remove(hashtag);
+                    }
+                }
+            }
+        }
+    });
+}
+
+fn is_hidden_by(viewer: Principal, post_id: u64) -> bool {
+    HIDDEN_POSTS.with(|hidden| hidden.borrow().get(&viewer).is_some_and(|posts| posts.contains(&post_id)))
+}
+
+fn recently_seen_by(viewer: Principal) -> HashSet<u64> {
+    SEEN_POSTS.with(|seen| seen.borrow().get(&viewer).map(|ids| ids.iter().copied().collect()).unwrap_or_default())
+}
+
+fn update_trending_topics(author: Principal, hashtags: &Vec<String>) {
     let current_time = time();
-    TRENDING_TOPICS.with(|topics| {
-        let mut topics = topics.borrow_mut();
-        for hashtag in hashtags {
+    for hashtag in hashtags {
+        let distinct_authors = TOPIC_AUTHORS.with(|authors| {
+            let mut authors = authors.borrow_mut();
+            let tag_authors = authors.entry(hashtag.clone()).or_default();
+            tag_authors.insert(author);
+            tag_authors.len() as u64
+        });
+        TRENDING_TOPICS.with(|topics| {
+            let mut topics = topics.borrow_mut();
             let topic = topics.entry(hashtag.clone()).or_insert_with(|| TrendingTopic {
                 hashtag: hashtag.clone(),
                 count: 0,
+                distinct_authors: 0,
                 last_used: current_time,
             });
             topic.count += 1;
+            topic.distinct_authors = distinct_authors;
             topic.last_used = current_time;
+        });
+    }
+    TOPIC_USAGE.with(|usage| {
+        let mut usage = usage.borrow_mut();
+        for hashtag in hashtags {
+            let uses = usage.entry(hashtag.clone()).or_default();
+            if uses.len() >= MAX_TOPIC_USAGE_SAMPLES {
+                uses.pop_front();
+            }
+            uses.push_back((current_time, author));
         }
     });
 }
 
-// Helper function to create posts (used by both create_post and reshare_post)
-fn create_post_internal(author: Principal, content: String, post_type: PostType) -> Result<Post, String> {
-    let post_id = get_next_id(&POST_COUNTER);
-    let hashtags: Vec<String> = content
-        .split_whitespace()
-        .filter(|word| word.starts_with('#'))
-        .map(|word| word.to_string())
-        .collect();
+// Undoes update_trending_topics for tags a post no longer carries. The usage sample taken at `used_at`
+// (the post's creation) is dropped, or the author's latest one if the tag came from an earlier edit.
+// The author stays counted as distinct while another of their posts still uses the tag.
+fn remove_from_trending_topics(author: Principal, hashtags: &[String], used_at: u64) {
+    for hashtag in hashtags {
+        let still_used = POSTS.with(|posts| posts.borrow().values().any(|post| post.author == author && post.hashtags.contains(hashtag)));
+        let distinct_authors = TOPIC_AUTHORS.with(|authors| {
+            let mut authors = authors.borrow_mut();
+            let remaining = match authors.get_mut(hashtag) {
+                Some(tag_authors) => {
+                    if !still_used {
+                        tag_authors.remove(&author);
+                    }
+                    tag_authors.len() as u64
+                }
+                None => 0,
+            };
+            if remaining == 0 {
+                authors.remove(hashtag);
+            }
+            remaining
+        });
+        TRENDING_TOPICS.with(|topics| {
+            let mut topics = topics.borrow_mut();
+            if let Some(topic) = topics.get_mut(hashtag) {
+                topic.count = topic.count.saturating_sub(1);
+                topic.distinct_authors = distinct_authors;
+                if topic.count == 0 {
+                    topics.remove(hashtag);
+                }
+            }
+        });
+        TOPIC_USAGE.with(|usage| {
+            let mut usage = usage.borrow_mut();
+            if let Some(uses) = usage.get_mut(hashtag) {
+                let sample = uses.iter().rposition(|entry| *entry == (used_at, author))
+                    .or_else(|| uses.iter().rposition(|(_, user)| *user == author));
+                if let Some(index) = sample {
+                    uses.remove(index);
+                }
+                if uses.is_empty() {
+                    usage.remove(hashtag);
+                }
+            }
+        });
+    }
+}
 
-    let post = Post {
-        id: post_id,
-        author,
-        content,
-        created_at: time(),
-        likes: Vec::new(),
-        comments: Vec::new(),
-        hashtags: hashtags.clone(),
-        post_type,
-        reshare_count: 0,
-    };
+// Distinct authors, decayed hyperbolically by time since the tag was last used
+fn trending_score(topic: &TrendingTopic, now: u64) -> u64 {
+    let idle = now.saturating_sub(topic.last_used);
+    let score = topic.distinct_authors as u128 * TRENDING_SCORE_SCALE as u128 * TRENDING_DECAY_NS as u128
+        / (TRENDING_DECAY_NS as u128 + idle as u128);
+    score as u64
+}
 
-    POSTS.with(|posts| {
-        posts.borrow_mut().insert(post_id, post.clone());
+fn sort_by_trending_score(topics: &mut [TrendingTopic], now: u64) {
+    topics.sort_by(|a, b| {
+        trending_score(b, now).cmp(&trending_score(a, now))
+            .then_with(|| b.count.cmp(&a.count))
+            .then_with(|| a.hashtag.cmp(&b.hashtag))
     });
-
-    update_trending_topics(&hashtags);
-    update_content_affinity(author, &hashtags, 1);
-
-    Result::Ok(post)
 }
 
-// Basic functions
-#[query]
-fn greet(name: String) -> String {
-    format!("Hello, {}! Welcome to ToknTalk!", name)
+// Expects the deduplicated, normalized tags from parse_hashtags
+fn update_hashtag_cooccurrence(hashtags: &[String]) {
+    HASHTAG_COOCCURRENCE.with(|cooccurrence| {
+        let mut cooccurrence = cooccurrence.borrow_mut();
+        for tag in hashtags {
+            let related = cooccurrence.entry(tag.clone()).or_default();
+            for other in hashtags.iter().filter(|other| *other != tag) {
+                *related.entry(other.clone()).or_insert(0) += 1;
+            }
+        }
+    });
 }
 
-#[query]
-fn get_counter() -> u64 {
-    COUNTER.with(|counter| *counter.borrow())
-}
+// Reverses update_hashtag_cooccurrence for a post's previous tag set; pairs that drop to zero are forgotten
+fn reduce_hashtag_cooccurrence(hashtags: &[String]) {
+    HASHTAG_COOCCURRENCE.with(|cooccurrence| {
+        let mut cooccurrence = cooccurrence.borrow_mut();
+        for tag in hashtags {
+            if let Some(related) = cooccurrence.get_mut(tag) {
+                for other in hashtags.iter().filter(|other| *other != tag) {
+                    if let Some(count) = related.get_mut(other) {
+                        *count = count.saturating_sub(1);
+                        if *count == 0 {
+                            related.remove(other);
+                        }
+                    }
+                }
+                if related.is_empty() {
+                    cooccurrence.remove(tag);
+                }
+            }
+        }
+    });
+}
+
+// Lowercase form without alias resolution
+fn lowercase_hashtag(word: &str) -> Option<String> {
+    let tag: String = word
+        .trim_start_matches('#')
+        .chars()
+        .take_while(|c| c.is_alphanumeric() || *c == '_')
+        .collect();
+    if tag.is_empty() {
+        None
+    } else {
+        Some(format!("#{}", tag.to_lowercase()))
+    }
+}
+
+// Normalizes a single `#tag` token: strips leading hashes and trailing punctuation, lowercases the rest,
+// then maps it through HASHTAG_ALIASES. Returns None when nothing tag-like remains (e.g. "####").
+fn normalize_hashtag(word: &str) -> Option<String> {
+    let tag = lowercase_hashtag(word)?;
+    Some(HASHTAG_ALIASES.with(|aliases| aliases.borrow().get(&tag).cloned()).unwrap_or(tag))
+}
+
+fn canonical_hashtag(tag: &str) -> String {
+    normalize_hashtag(tag).unwrap_or_else(|| tag.to_string())
+}
+
+fn merge_tag_counts(counts: HashMap<String, u64>) -> HashMap<String, u64> {
+    let mut merged: HashMap<String, u64> = HashMap::new();
+    for (tag, count) in counts {
+        *merged.entry(canonical_hashtag(&tag)).or_insert(0) += count;
+    }
+    merged
+}
+
+// Rewrites every stored tag into its canonical form, merging entries that now collide.
+// Returns how many posts had their tags changed.
+fn migrate_stored_hashtags() -> u64 {
+    let changed_posts = POSTS.with(|posts| {
+        let mut changed = 0;
+        for post in posts.borrow_mut().values_mut() {
+            let mut canonical: Vec<String> = Vec::new();
+            for tag in post.hashtags.iter().map(|tag| canonical_hashtag(tag)) {
+                if !canonical.contains(&tag) {
+                    canonical.push(tag);
+                }
+            }
+            if canonical != post.hashtags {
+                post.hashtags = canonical;
+                changed += 1;
+            }
+        }
+        changed
+    });
+
+    TOPIC_AUTHORS.with(|authors| {
+        let mut authors = authors.borrow_mut();
+        let mut merged: HashMap<String, HashSet<Principal>> = HashMap::new();
+        for (tag, tag_authors) in authors.drain() {
+            merged.entry(canonical_hashtag(&tag)).or_default().extend(tag_authors);
+        }
+        *authors = merged;
+    });
+    TRENDING_TOPICS.with(|topics| {
+        let mut topics = topics.borrow_mut();
+        let mut merged: HashMap<String, TrendingTopic> = HashMap::new();
+        for (tag, topic) in topics.drain() {
+            let hashtag = canonical_hashtag(&tag);
+            let entry = merged.entry(hashtag.clone()).or_insert_with(|| TrendingTopic {
+                hashtag,
+                count: 0,
+                distinct_authors: 0,
+                last_used: topic.last_used,
+            });
+            entry.count += topic.count;
+            entry.last_used = entry.last_used.max(topic.last_used);
+        }
+        for topic in merged.values_mut() {
+            topic.distinct_authors = TOPIC_AUTHORS.with(|authors| authors.borrow().get(&topic.hashtag).map_or(0, |set| set.len() as u64));
+        }
+        *topics = merged;
+    });
+    TOPIC_USAGE.with(|usage| {
+        let mut usage = usage.borrow_mut();
+        let mut merged: HashMap<String, Vec<(u64, Principal)>> = HashMap::new();
+        for (tag, uses) in usage.drain() {
+            merged.entry(canonical_hashtag(&tag)).or_default().extend(uses);
+        }
+        *usage = merged.into_iter()
+            .map(|(tag, mut uses)| {
+                uses.sort_by_key(|(used_at, _)| *used_at);
+                let skip = uses.len().saturating_sub(MAX_TOPIC_USAGE_SAMPLES);
+                (tag, uses.into_iter().skip(skip).collect())
+            })
+            .collect();
+    });
+    HASHTAG_COOCCURRENCE.with(|cooccurrence| {
+        let mut cooccurrence = cooccurrence.borrow_mut();
+        let mut merged: HashMap<String, HashMap<String, u64>> = HashMap::new();
+        for (tag, related) in cooccurrence.drain() {
+            let tag = canonical_hashtag(&tag);
+            let entry = merged.entry(tag.clone()).or_default();
+            for (other, count) in merge_tag_counts(related) {
+                if other != tag {
+                    *entry.entry(other).or_insert(0) += count;
+                }
+            }
+        }
+        *cooccurrence = merged;
+    });
+    for per_user in [&CONTENT_AFFINITY, &HIDDEN_TOPICS] {
+        per_user.with(|per_user| {
+            for counts in per_user.borrow_mut().values_mut() {
+                *counts = merge_tag_counts(std::mem::take(counts));
+            }
+        });
+    }
+    MUTED_HASHTAGS.with(|muted| {
+        for tags in muted.borrow_mut().values_mut() {
+            let mut canonical: Vec<String> = Vec::new();
+            for tag in tags.iter().map(|tag| canonical_hashtag(tag)) {
+                if !canonical.contains(&tag) {
+                    canonical.push(tag);
+                }
+            }
+            *tags = canonical;
+        }
+    });
+
+    changed_posts
+}
+
+fn parse_hashtags(content: &str) -> Vec<String> {
+    let mut hashtags: Vec<String> = Vec::new();
+    for word in content.split_whitespace().filter(|word| word.starts_with('#')) {
+        if let Some(tag) = normalize_hashtag(word) {
+            if !hashtags.contains(&tag) {
+                hashtags.push(tag);
+            }
+        }
+    }
+    hashtags
+}
+
+// Lowercased `@handle`s in order of first appearance; trailing punctuation (including a final '.') is dropped
+fn parse_mention_handles(content: &str) -> Vec<String> {
+    let mut handles: Vec<String> = Vec::new();
+    for word in content.split_whitespace().filter(|word| word.starts_with('@')) {
+        let handle: String = word[1..]
+            .chars()
+            .take_while(|c| c.is_alphanumeric() || *c == '_' || *c == '.')
+            .collect();
+        let handle = handle.trim_end_matches('.').to_lowercase();
+        if !handle.is_empty() && !handles.contains(&handle) {
+            handles.push(handle);
+        }
+    }
+    handles
+}
+
+// Handles that don't belong to anyone are ignored
+fn parse_mentions(content: &str) -> Vec<Principal> {
+    let handles = parse_mention_handles(content);
+    USERNAMES.with(|usernames| {
+        let usernames = usernames.borrow();
+        let mut mentions: Vec<Principal> = Vec::new();
+        for user in handles.iter().filter_map(|handle| usernames.get(handle)) {
+            if !mentions.contains(user) {
+                mentions.push(*user);
+            }
+        }
+        mentions
+    })
+}
+
+// Indexes the post for each mentioned user and notifies those who can see it
+fn record_mentions(post: &Post, mentioned: &[Principal]) {
+    MENTIONS_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        for user in mentioned {
+            index.entry(*user).or_default().push(post.id);
+        }
+    });
+
+    for &user in mentioned {
+        if user == post.author || is_blocked_between(user, post.author) || !is_visible_to(post, user) {
+            continue;
+        }
+        let notification_id = get_next_id(&NOTIFICATION_COUNTER);
+        let notification = Notification {
+            id: notification_id,
+            recipient: user,
+            notification_type: NotificationType::Mention { post_id: post.id, user_id: post.author },
+            created_at: time(),
+            read: false,
+        };
+        insert_notification(notification);
+    }
+}
+
+fn unindex_mentions(post_id: u64, mentioned: &[Principal]) {
+    MENTIONS_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        for user in mentioned {
+            if let Some(post_ids) = index.get_mut(user) {
+                post_ids.retain(|&id| id != post_id);
+            }
+        }
+    });
+}
+
+fn notification_kind(notification_type: &NotificationType) -> &'static str {
+    match notification_type {
+        NotificationType::Follow { .. } => "Follow",
+        NotificationType::Like { .. } => "Like",
+        NotificationType::Comment { .. } => "Comment",
+        NotificationType::Message { .. } => "Message",
+        NotificationType::Mention { .. } => "Mention",
+        NotificationType::Reshare { .. } => "Reshare",
+        NotificationType::Reaction { .. } => "Reaction",
+        NotificationType::CommentLike { .. } => "CommentLike",
+        NotificationType::CommentQuote { .. } => "CommentQuote",
+        NotificationType::Reply { .. } => "Reply",
+    }
+}
+
+fn notification_actor(notification_type: &NotificationType) -> Principal {
+    match notification_type {
+        NotificationType::Follow { user_id }
+        | NotificationType::Like { user_id, .. }
+        | NotificationType::Comment { user_id, .. }
+        | NotificationType::Message { user_id, .. }
+        | NotificationType::Mention { user_id, .. }
+        | NotificationType::Reshare { user_id, .. }
+        | NotificationType::Reaction { user_id, .. }
+        | NotificationType::CommentLike { user_id, .. }
+        | NotificationType::CommentQuote { user_id, .. }
+        | NotificationType::Reply { user_id, .. } => *user_id,
+    }
+}
+
+// Only likes and reactions are grouped; everything else stays one row per notification
+fn notification_group_post(notification_type: &NotificationType) -> Option<u64> {
+    match notification_type {
+        NotificationType::Like { post_id, .. } | NotificationType::Reaction { post_id, .. } => Some(*post_id),
+        _ => None,
+    }
+}
+
+// 1:1 thread ids are the two principals in sorted order, so both sides resolve to the same thread
+fn get_thread_id(user_a: Principal, user_b: Principal) -> String {
+    if user_a < user_b {
+        format!("{}_{}", user_a, user_b)
+    } else {
+        format!("{}_{}", user_b, user_a)
+    }
+}
+
+// Users without a profile are treated as active so their content keeps showing
+fn is_deactivated(user: Principal) -> bool {
+    PROFILES.with(|profiles| profiles.borrow().get(&user).is_some_and(|profile| !profile.active))
+}
+
+// Central visibility check for anything that returns posts to a viewer
+fn is_visible_to(post: &Post, viewer: Principal) -> bool {
+    if is_deactivated(post.author) {
+        return false;
+    }
+    if post.hidden_by_reports && viewer != post.author {
+        return false;
+    }
+    match post.visibility {
+        Visibility::Public => true,
+        Visibility::CloseFriends => {
+            viewer == post.author || CLOSE_FRIENDS.with(|close_friends| {
+                close_friends.borrow().get(&post.author).is_some_and(|friends| friends.contains(&viewer))
+            })
+        }
+    }
+}
+
+fn is_following_user(follower: Principal, user: Principal) -> bool {
+    FOLLOWS.with(|follows| follows.borrow().get(&follower).is_some_and(|following| following.contains(&user)))
+}
+
+type ActionLog = std::thread::LocalKey<RefCell<HashMap<Principal, VecDeque<u64>>>>;
+
+// Sliding-window limiter. Each log only ever holds a user's last `max` actions, so if it's full and its
+// oldest entry is still inside the window, the user is over the limit. Returns how long until they aren't.
+fn rate_limit_wait(log: &'static ActionLog, user: Principal, now: u64, max: usize, window_ns: u64) -> Option<u64> {
+    let oldest = log.with(|log| {
+        log.borrow().get(&user)
+            .filter(|timestamps| timestamps.len() >= max)
+            .and_then(|timestamps| timestamps.front().copied())
+    })?;
+    let elapsed = now.saturating_sub(oldest);
+    (elapsed < window_ns).then_some(window_ns - elapsed)
+}
+
+fn record_action(log: &'static ActionLog, user: Principal, now: u64, max: usize) {
+    log.with(|log| {
+        let mut log = log.borrow_mut();
+        let timestamps = log.entry(user).or_default();
+        if timestamps.len() >= max {
+            timestamps.pop_front();
+        }
+        timestamps.push_back(now);
+    });
+}
+
+// Rounded up, so "try again in" never undershoots
+fn format_wait(wait_ns: u64) -> String {
+    let secs = wait_ns.div_ceil(1_000_000_000);
+    if secs >= 120 {
+        format!("{} minutes", secs.div_ceil(60))
+    } else {
+        format!("{} seconds", secs)
+    }
+}
+
+fn check_rate_limit(log: &'static ActionLog, user: Principal, now: u64, max: usize, window_ns: u64, what: &str, per: &str) -> Result<(), String> {
+    match rate_limit_wait(log, user, now, max, window_ns) {
+        Some(wait_ns) => Result::Err(format!("Too many {} ({} per {}); try again in {}", what, max, per, format_wait(wait_ns))),
+        None => Result::Ok(()),
+    }
+}
+
+// Checks and, if allowed, counts the action in one step; for actions that can't fail afterwards
+fn enforce_rate_limit(log: &'static ActionLog, user: Principal, now: u64, max: usize, window_ns: u64, what: &str, per: &str) -> Result<(), String> {
+    if let Result::Err(e) = check_rate_limit(log, user, now, max, window_ns, what, per) {
+        return Result::Err(e);
+    }
+    record_action(log, user, now, max);
+    Result::Ok(())
+}
+
+fn has_blocked(blocker: Principal, user: Principal) -> bool {
+    BLOCKS.with(|blocks| blocks.borrow().get(&blocker).is_some_and(|blocked| blocked.contains(&user)))
+}
+
+// True if either user has blocked the other
+fn is_blocked_between(user_a: Principal, user_b: Principal) -> bool {
+    has_blocked(user_a, user_b) || has_blocked(user_b, user_a)
+}
+
+fn is_verified(user: Principal) -> bool {
+    PROFILES.with(|profiles| profiles.borrow().get(&user).is_some_and(|profile| profile.verified))
+}
+
+// Adds or removes a user's contribution to the follower/following counts of everyone they're connected to
+fn adjust_follow_counts(user: Principal, restore: bool) {
+    let following = FOLLOWS.with(|follows| follows.borrow().get(&user).cloned().unwrap_or_default());
+    let followers = get_followers(user);
+
+    PROFILES.with(|profiles| {
+        let mut profiles = profiles.borrow_mut();
+        for followed in following {
+            if let Some(profile) = profiles.get_mut(&followed) {
+                profile.followers_count = if restore { profile.followers_count + 1 } else { profile.followers_count.saturating_sub(1) };
+            }
+        }
+        for follower in followers {
+            if let Some(profile) = profiles.get_mut(&follower) {
+                profile.following_count = if restore { profile.following_count + 1 } else { profile.following_count.saturating_sub(1) };
+            }
+        }
+    });
+}
+
+fn max_post_length() -> usize {
+    MAX_POST_LENGTH.with(|max| *max.borrow())
+}
+
+fn validate_content(content: &str, max_chars: usize, label: &str) -> Result<(), String> {
+    if content.trim().is_empty() {
+        return Result::Err(format!("{} cannot be empty", label));
+    }
+    let length = content.chars().count();
+    if length > max_chars {
+        return Result::Err(format!("{} is too long ({} characters, maximum is {})", label, length, max_chars));
+    }
+    Result::Ok(())
+}
+
+// Matches whole alphanumeric runs only, so a filtered word inside a longer word is left alone
+fn apply_content_filter(content: &str, words: &HashSet<String>, mode: &ContentFilterMode) -> Result<String, String> {
+    if *mode == ContentFilterMode::Off || words.is_empty() {
+        return Result::Ok(content.to_string());
+    }
+
+    let mut filtered = String::with_capacity(content.len());
+    let mut word = String::new();
+    let mut chars = content.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_alphanumeric() {
+            word.push(c);
+            if chars.peek().is_some_and(|next| next.is_alphanumeric()) {
+                continue;
+            }
+            if words.contains(&word.to_lowercase()) {
+                if *mode == ContentFilterMode::Reject {
+                    return Result::Err(format!("Content contains a filtered word: \"{}\"", word));
+                }
+                filtered.extend(std::iter::repeat_n('*', word.chars().count()));
+            } else {
+                filtered.push_str(&word);
+            }
+            word.clear();
+        } else {
+            filtered.push(c);
+        }
+    }
+    Result::Ok(filtered)
+}
+
+fn filter_content(content: String) -> Result<String, String> {
+    let mode = CONTENT_FILTER_MODE.with(|mode| mode.borrow().clone());
+    if mode == ContentFilterMode::Off {
+        return Result::Ok(content);
+    }
+    CONTENT_FILTER_WORDS.with(|words| apply_content_filter(&content, &words.borrow(), &mode))
+}
+
+fn looks_like_link_spam(content: &str) -> bool {
+    let words: Vec<&str> = content.split_whitespace().collect();
+    let links = words.iter()
+        .filter(|word| {
+            let word = word.to_lowercase();
+            word.starts_with("http://") || word.starts_with("https://") || word.starts_with("www.")
+        })
+        .count();
+    links > MAX_LINKS_BEFORE_FLAG || (links >= MIN_LINKS_FOR_RATIO_FLAG && links * 2 > words.len())
+}
+
+fn validate_attachments(attachments: &[MediaAttachment], max: usize) -> Result<(), String> {
+    if attachments.len() > max {
+        return Result::Err(format!("At most {} attachments are allowed", max));
+    }
+    if attachments.iter().any(|attachment| attachment.url.trim().is_empty()) {
+        return Result::Err("Attachment URL cannot be empty".to_string());
+    }
+    Result::Ok(())
+}
+
+// Absolute http(s) URL with a dotted host and no whitespace
+fn validate_profile_link(link: &ProfileLink) -> Result<(), String> {
+    if let Result::Err(e) = validate_content(&link.label, MAX_LINK_LABEL_LENGTH, "Link label") {
+        return Result::Err(e);
+    }
+    let url = link.url.trim();
+    if url.chars().count() > MAX_LINK_URL_LENGTH {
+        return Result::Err(format!("Link URL is too long (maximum is {} characters)", MAX_LINK_URL_LENGTH));
+    }
+    let rest = match url.strip_prefix("https://").or_else(|| url.strip_prefix("http://")) {
+        Some(rest) => rest,
+        None => return Result::Err(format!("Link URL must start with http:// or https://: {}", url)),
+    };
+    let host = rest.split(['/', '?', '#']).next().unwrap_or("");
+    let valid_host = !host.is_empty()
+        && host.contains('.')
+        && !host.starts_with('.')
+        && !host.ends_with('.')
+        && host.chars().all(|c| c.is_alphanumeric() || c == '.' || c == '-' || c == ':');
+    if !valid_host || url.chars().any(char::is_whitespace) {
+        return Result::Err(format!("Invalid link URL: {}", url));
+    }
+    Result::Ok(())
+}
+
+fn validate_username(username: &str) -> Result<(), String> {
+    let length = username.chars().count();
+    if !(MIN_USERNAME_LENGTH..=MAX_USERNAME_LENGTH).contains(&length) {
+        return Result::Err(format!("Username must be between {} and {} characters", MIN_USERNAME_LENGTH, MAX_USERNAME_LENGTH));
+    }
+    if !username.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '.') {
+        return Result::Err("Username may only contain letters, numbers, '_' and '.'".to_string());
+    }
+    Result::Ok(())
+}
+
+// Checks that `username` is free, or already belongs to `user`
+fn is_username_available(username: &str, user: Principal) -> bool {
+    USERNAMES.with(|usernames| {
+        usernames.borrow().get(&username.to_lowercase()).is_none_or(|owner| *owner == user)
+    })
+}
+
+fn is_admin(user: Principal) -> bool {
+    ADMINS.with(|admins| admins.borrow().contains(&user))
+}
+
+// Helper function to create posts (used by both create_post and reshare_post)
+fn create_post_internal(author: Principal, content: String, post_type: PostType, media: Vec<MediaAttachment>, visibility: Visibility, content_warning: Option<String>) -> Result<Post, String> {
+    let content = match filter_content(content) {
+        Result::Ok(content) => content,
+        Result::Err(e) => return Result::Err(e),
+    };
+    let post_id = get_next_id(&POST_COUNTER);
+    let hashtags = parse_hashtags(&content);
+    let mentions = parse_mentions(&content);
+    let flagged = looks_like_link_spam(&content);
+
+    let post = Post {
+        id: post_id,
+        author,
+        content,
+        created_at: time(),
+        likes: Vec::new(),
+        comments: Vec::new(),
+        hashtags: hashtags.clone(),
+        mentions: mentions.clone(),
+        post_type,
+        reshare_count: 0,
+        media,
+        visibility,
+        content_warning,
+        flagged,
+        comments_enabled: true,
+        hidden_by_reports: false,
+    };
+
+    POSTS.with(|posts| {
+        posts.borrow_mut().insert(post_id, post.clone());
+    });
+
+    update_trending_topics(author, &hashtags);
+    update_hashtag_cooccurrence(&hashtags);
+    update_content_affinity(author, &hashtags, 1);
+    record_mentions(&post, &mentions);
+    mark_active(author);
+
+    Result::Ok(post)
+}
+
+// The deployer becomes the first admin
+#[init]
+fn init() {
+    let deployer = ic_cdk::caller();
+    ADMINS.with(|admins| admins.borrow_mut().push(deployer));
+    start_timers();
+}
+
+// Upgrades clear every timer, so they have to be armed again
+#[post_upgrade]
+fn post_upgrade() {
+    start_timers();
+}
+
+fn start_timers() {
+    ic_cdk_timers::set_timer_interval(ORPHANED_NOTIFICATION_SWEEP_INTERVAL, || {
+        remove_orphaned_notifications();
+    });
+}
+
+// Basic functions
+#[query]
+fn greet(name: String) -> String {
+    format!("Hello, {}! Welcome to ToknTalk!", name)
+}
+
+#[query]
+fn get_counter() -> u64 {
+    COUNTER.with(|counter| *counter.borrow())
+}
 
 #[update]
 fn increment_counter() -> u64 {
@@ -332,6 +1622,12 @@ fn create_profile(username: String, bio: Vec<String>, avatar_url: Vec<String>) -
     if PROFILES.with(|profiles| profiles.borrow().contains_key(&caller)) {
         return Result::Err("Profile already exists".to_string());
     }
+    if let Result::Err(e) = validate_username(&username) {
+        return Result::Err(e);
+    }
+    if !is_username_available(&username, caller) {
+        return Result::Err("Username is already taken".to_string());
+    }
 
     let profile = UserProfile {
         id: caller,
@@ -341,15 +1637,69 @@ fn create_profile(username: String, bio: Vec<String>, avatar_url: Vec<String>) -
         followers_count: 0,
         following_count: 0,
         created_at: time(),
+        active: true,
+        verified: false,
+        username_history: Vec::new(),
+        feed_preference: FeedAlgorithm::Chronological,
+        links: Vec::new(),
     };
 
     PROFILES.with(|profiles| {
         profiles.borrow_mut().insert(caller, profile.clone());
     });
+    USERNAMES.with(|usernames| {
+        usernames.borrow_mut().insert(profile.username.to_lowercase(), caller);
+    });
 
     Result::Ok(profile)
 }
 
+#[update]
+fn change_username(new_username: String) -> Result<UserProfile, String> {
+    let caller = ic_cdk::caller();
+    let now = time();
+
+    let current = match PROFILES.with(|profiles| profiles.borrow().get(&caller).map(|profile| profile.username.clone())) {
+        Some(username) => username,
+        None => return Result::Err("Profile not found".to_string()),
+    };
+
+    if current == new_username {
+        return Result::Err("New username is the same as the current one".to_string());
+    }
+    if let Result::Err(e) = validate_username(&new_username) {
+        return Result::Err(e);
+    }
+    if !is_username_available(&new_username, caller) {
+        return Result::Err("Username is already taken".to_string());
+    }
+
+    if let Some(last_change) = LAST_USERNAME_CHANGE.with(|changes| changes.borrow().get(&caller).copied()) {
+        let elapsed = now.saturating_sub(last_change);
+        if elapsed < USERNAME_CHANGE_COOLDOWN_NS {
+            let days_left = (USERNAME_CHANGE_COOLDOWN_NS - elapsed).div_ceil(24 * 60 * 60 * 1_000_000_000);
+            return Result::Err(format!("Username can only be changed once every 30 days ({} days remaining)", days_left));
+        }
+    }
+
+    USERNAMES.with(|usernames| {
+        let mut usernames = usernames.borrow_mut();
+        usernames.remove(&current.to_lowercase());
+        usernames.insert(new_username.to_lowercase(), caller);
+    });
+    LAST_USERNAME_CHANGE.with(|changes| {
+        changes.borrow_mut().insert(caller, now);
+    });
+
+    PROFILES.with(|profiles| {
+        let mut profiles = profiles.borrow_mut();
+        let profile = profiles.get_mut(&caller).expect("profile exists");
+        profile.username_history.push(current);
+        profile.username = new_username;
+        Result::Ok(profile.clone())
+    })
+}
+
 #[update]
 fn update_profile(bio: Option<Vec<String>>, avatar_url: Option<Vec<String>>) -> Result<UserProfile, String> {
     let caller = ic_cdk::caller();
@@ -370,11 +1720,95 @@ fn update_profile(bio: Option<Vec<String>>, avatar_url: Option<Vec<String>>) ->
     })
 }
 
-#[query]
-fn get_profile() -> Result<UserProfile, String> {
+// Replaces all of the caller's links
+#[update]
+fn set_profile_links(links: Vec<ProfileLink>) -> Result<UserProfile, String> {
     let caller = ic_cdk::caller();
+    if links.len() > MAX_PROFILE_LINKS {
+        return Result::Err(format!("At most {} links are allowed", MAX_PROFILE_LINKS));
+    }
+    for link in &links {
+        if let Result::Err(e) = validate_profile_link(link) {
+            return Result::Err(e);
+        }
+    }
+    let links: Vec<ProfileLink> = links.into_iter()
+        .map(|link| ProfileLink { label: link.label.trim().to_string(), url: link.url.trim().to_string() })
+        .collect();
+
     PROFILES.with(|profiles| {
-        profiles.borrow().get(&caller).cloned().map(Result::Ok).unwrap_or(Result::Err("Profile not found".to_string()))
+        match profiles.borrow_mut().get_mut(&caller) {
+            Some(profile) => {
+                profile.links = links;
+                Result::Ok(profile.clone())
+            }
+            None => Result::Err("Profile not found".to_string()),
+        }
+    })
+}
+
+#[update]
+fn set_feed_preference(algorithm: FeedAlgorithm) -> Result<UserProfile, String> {
+    let caller = ic_cdk::caller();
+    PROFILES.with(|profiles| {
+        match profiles.borrow_mut().get_mut(&caller) {
+            Some(profile) => {
+                profile.feed_preference = algorithm;
+                Result::Ok(profile.clone())
+            }
+            None => Result::Err("Profile not found".to_string()),
+        }
+    })
+}
+
+// Soft-deletes the caller's profile; data is kept so reactivate_profile can restore it
+#[update]
+fn deactivate_profile() -> Result<(), String> {
+    let caller = ic_cdk::caller();
+
+    let result = PROFILES.with(|profiles| {
+        match profiles.borrow_mut().get_mut(&caller) {
+            Some(profile) if !profile.active => Result::Err("Profile already deactivated".to_string()),
+            Some(profile) => {
+                profile.active = false;
+                Result::Ok(())
+            }
+            None => Result::Err("Profile not found".to_string()),
+        }
+    });
+
+    if let Result::Ok(()) = result {
+        adjust_follow_counts(caller, false);
+    }
+    result
+}
+
+#[update]
+fn reactivate_profile() -> Result<(), String> {
+    let caller = ic_cdk::caller();
+
+    let result = PROFILES.with(|profiles| {
+        match profiles.borrow_mut().get_mut(&caller) {
+            Some(profile) if profile.active => Result::Err("Profile is already active".to_string()),
+            Some(profile) => {
+                profile.active = true;
+                Result::Ok(())
+            }
+            None => Result::Err("Profile not found".to_string()),
+        }
+    });
+
+    if let Result::Ok(()) = result {
+        adjust_follow_counts(caller, true);
+    }
+    result
+}
+
+#[query]
+fn get_profile() -> Result<UserProfile, String> {
+    let caller = ic_cdk::caller();
+    PROFILES.with(|profiles| {
+        profiles.borrow().get(&caller).cloned().map(Result::Ok).unwrap_or(Result::Err("Profile not found".to_string()))
     })
 }
 
@@ -385,11 +1819,318 @@ fn get_user_profile(user_id: Principal) -> Result<UserProfile, String> {
     })
 }
 
+// Batch lookup for rendering timelines; keeps input order and skips users without a profile
+#[query]
+fn get_user_profiles(user_ids: Vec<Principal>) -> Vec<UserProfile> {
+    PROFILES.with(|profiles| {
+        let profiles = profiles.borrow();
+        user_ids.iter()
+            .filter_map(|user_id| profiles.get(user_id).cloned())
+            .collect()
+    })
+}
+
+#[update]
+fn record_profile_view(user_id: Principal) -> Result<(), String> {
+    let viewer = ic_cdk::caller();
+
+    // Self-views don't count
+    if viewer == user_id {
+        return Result::Ok(());
+    }
+
+    if !PROFILES.with(|profiles| profiles.borrow().contains_key(&user_id)) {
+        return Result::Err("Profile not found".to_string());
+    }
+
+    let now = time();
+    let counted = LAST_PROFILE_VIEW.with(|last_views| {
+        let mut last_views = last_views.borrow_mut();
+        let last_view = last_views.entry((viewer, user_id)).or_insert(0);
+        if *last_view != 0 && now.saturating_sub(*last_view) < PROFILE_VIEW_DEDUP_WINDOW_NS {
+            false
+        } else {
+            *last_view = now;
+            true
+        }
+    });
+
+    if counted {
+        PROFILE_VIEWS.with(|views| {
+            *views.borrow_mut().entry(user_id).or_insert(0) += 1;
+        });
+    }
+
+    Result::Ok(())
+}
+
+#[query]
+fn get_profile_view_count() -> u64 {
+    let caller = ic_cdk::caller();
+    PROFILE_VIEWS.with(|views| views.borrow().get(&caller).copied().unwrap_or(0))
+}
+
+// Reshares the user made count toward post_count; engagement totals are on the user's own posts
+#[query]
+fn get_profile_stats(user_id: Principal) -> ProfileStats {
+    let mut stats = ProfileStats {
+        user_id,
+        post_count: 0,
+        likes_received: 0,
+        comments_received: 0,
+        reshares_received: 0,
+        followers_count: 0,
+        following_count: 0,
+    };
+
+    POSTS.with(|posts| {
+        for post in posts.borrow().values().filter(|post| post.author == user_id) {
+            stats.post_count += 1;
+            stats.likes_received += post.likes.len() as u64;
+            stats.comments_received += post.comments.len() as u64;
+            stats.reshares_received += post.reshare_count;
+        }
+    });
+
+    if let Some(profile) = PROFILES.with(|profiles| profiles.borrow().get(&user_id).cloned()) {
+        stats.followers_count = profile.followers_count;
+        stats.following_count = profile.following_count;
+    }
+
+    stats
+}
+
+fn profile_completeness(profile: &UserProfile, has_post: bool) -> ProfileCompleteness {
+    let has_text = |values: &Vec<String>| values.iter().any(|value| !value.trim().is_empty());
+    let checks = [
+        ("bio", has_text(&profile.bio)),
+        ("avatar", has_text(&profile.avatar_url)),
+        ("followers", profile.followers_count >= PROFILE_MIN_FOLLOWERS),
+        ("following", profile.following_count >= PROFILE_MIN_FOLLOWING),
+        ("post", has_post),
+    ];
+    let met = checks.iter().filter(|(_, done)| *done).count();
+    ProfileCompleteness {
+        score: (met * 100 / checks.len()) as u8,
+        missing: checks.iter().filter(|(_, done)| !*done).map(|(key, _)| key.to_string()).collect(),
+    }
+}
+
+#[query]
+fn get_profile_completeness() -> Result<ProfileCompleteness, String> {
+    let caller = ic_cdk::caller();
+    let profile = match PROFILES.with(|profiles| profiles.borrow().get(&caller).cloned()) {
+        Some(profile) => profile,
+        None => return Result::Err("Profile not found".to_string()),
+    };
+    let has_post = POSTS.with(|posts| posts.borrow().values().any(|post| post.author == caller));
+    Result::Ok(profile_completeness(&profile, has_post))
+}
+
+#[update]
+fn set_verified(user_id: Principal, verified: bool) -> Result<(), String> {
+    if !is_admin(ic_cdk::caller()) {
+        return Result::Err("Not authorized".to_string());
+    }
+
+    PROFILES.with(|profiles| {
+        if let Some(profile) = profiles.borrow_mut().get_mut(&user_id) {
+            profile.verified = verified;
+            Result::Ok(())
+        } else {
+            Result::Err("Profile not found".to_string())
+        }
+    })
+}
+
+#[query]
+fn get_verified_users(limit: u64) -> Vec<UserProfile> {
+    PROFILES.with(|profiles| {
+        let mut verified: Vec<UserProfile> = profiles.borrow().values()
+            .filter(|profile| profile.verified && profile.active)
+            .cloned()
+            .collect();
+        verified.sort_by_key(|profile| std::cmp::Reverse(profile.followers_count));
+        verified.truncate(limit as usize);
+        verified
+    })
+}
+
 // Post functions
 #[update]
 fn create_post(content: String) -> Result<Post, String> {
     let author = ic_cdk::caller();
-    create_post_internal(author, content, PostType::Original)
+    if let Result::Err(e) = validate_content(&content, max_post_length(), "Post") {
+        return Result::Err(e);
+    }
+    create_post_internal(author, content, PostType::Original, Vec::new(), Visibility::Public, None)
+}
+
+// Posts `content` with the comment embedded; the comment's author is notified
+#[update]
+fn quote_comment(comment_id: u64, content: String) -> Result<Post, String> {
+    let author = ic_cdk::caller();
+    if let Result::Err(e) = validate_content(&content, max_post_length(), "Post") {
+        return Result::Err(e);
+    }
+    let comment = match COMMENTS.with(|comments| comments.borrow().get(&comment_id).cloned()) {
+        Some(comment) => comment,
+        None => return Result::Err("Comment not found".to_string()),
+    };
+    let parent_visible = POSTS.with(|posts| posts.borrow().get(&comment.post_id).is_some_and(|post| is_visible_to(post, author)));
+    if !parent_visible {
+        return Result::Err("Comment not found".to_string());
+    }
+    if is_blocked_between(author, comment.author) {
+        return Result::Err("Cannot quote this comment".to_string());
+    }
+
+    let post_type = PostType::CommentQuote { comment_id, comment_author: comment.author, post_id: comment.post_id };
+    let post = match create_post_internal(author, content, post_type, Vec::new(), Visibility::Public, None) {
+        Result::Ok(post) => post,
+        Result::Err(e) => return Result::Err(e),
+    };
+
+    if comment.author != author {
+        let notification_id = get_next_id(&NOTIFICATION_COUNTER);
+        insert_notification(Notification {
+            id: notification_id,
+            recipient: comment.author,
+            notification_type: NotificationType::CommentQuote { post_id: post.id, comment_id, user_id: author },
+            created_at: time(),
+            read: false,
+        });
+    }
+
+    Result::Ok(post)
+}
+
+#[update]
+fn create_post_with_visibility(content: String, visibility: Visibility) -> Result<Post, String> {
+    let author = ic_cdk::caller();
+    if let Result::Err(e) = validate_content(&content, max_post_length(), "Post") {
+        return Result::Err(e);
+    }
+    create_post_internal(author, content, PostType::Original, Vec::new(), visibility, None)
+}
+
+#[update]
+fn create_post_with_media(content: String, media: Vec<MediaAttachment>) -> Result<Post, String> {
+    let author = ic_cdk::caller();
+
+    if let Result::Err(e) = validate_attachments(&media, MAX_POST_MEDIA) {
+        return Result::Err(e);
+    }
+    // Media-only posts are allowed, but the caption still has to respect the limit
+    if media.is_empty() || !content.trim().is_empty() {
+        if let Result::Err(e) = validate_content(&content, max_post_length(), "Post") {
+            return Result::Err(e);
+        }
+    }
+
+    create_post_internal(author, content, PostType::Original, media, Visibility::Public, None)
+}
+
+#[update]
+fn create_post_with_warning(content: String, warning: String) -> Result<Post, String> {
+    let author = ic_cdk::caller();
+    if let Result::Err(e) = validate_content(&content, max_post_length(), "Post") {
+        return Result::Err(e);
+    }
+    if let Result::Err(e) = validate_content(&warning, MAX_CONTENT_WARNING_LENGTH, "Content warning") {
+        return Result::Err(e);
+    }
+    create_post_internal(author, content, PostType::Original, Vec::new(), Visibility::Public, Some(warning.trim().to_string()))
+}
+
+fn set_comments_enabled_for(caller: Principal, post_id: u64, enabled: bool) -> Result<Post, String> {
+    POSTS.with(|posts| {
+        match posts.borrow_mut().get_mut(&post_id) {
+            Some(post) if post.author == caller => {
+                post.comments_enabled = enabled;
+                Result::Ok(post.clone())
+            }
+            Some(_) => Result::Err("Not authorized".to_string()),
+            None => Result::Err("Post not found".to_string()),
+        }
+    })
+}
+
+#[update]
+fn set_comments_enabled(post_id: u64, enabled: bool) -> Result<Post, String> {
+    set_comments_enabled_for(ic_cdk::caller(), post_id, enabled)
+}
+
+// Checked by add_comment and add_reply before anything is stored
+// Posts the commenter can't see are reported as missing rather than closed
+fn ensure_comments_open(post_id: u64, commenter: Principal) -> Result<(), String> {
+    let comments_enabled = POSTS.with(|posts| {
+        posts.borrow().get(&post_id).filter(|post| is_visible_to(post, commenter)).map(|post| post.comments_enabled)
+    });
+    match comments_enabled {
+        Some(true) => Result::Ok(()),
+        Some(false) => Result::Err("The author has turned off comments on this post".to_string()),
+        None => Result::Err("Post not found".to_string()),
+    }
+}
+
+// Replaces the text and warning of the caller's own post; passing no warning clears it
+#[update]
+fn edit_post(post_id: u64, content: String, content_warning: Option<String>) -> Result<Post, String> {
+    let caller = ic_cdk::caller();
+    if let Result::Err(e) = validate_content(&content, max_post_length(), "Post") {
+        return Result::Err(e);
+    }
+    if let Some(warning) = &content_warning {
+        if let Result::Err(e) = validate_content(warning, MAX_CONTENT_WARNING_LENGTH, "Content warning") {
+            return Result::Err(e);
+        }
+    }
+    let content = match filter_content(content) {
+        Result::Ok(content) => content,
+        Result::Err(e) => return Result::Err(e),
+    };
+
+    let mentions = parse_mentions(&content);
+    let edited = POSTS.with(|posts| {
+        let mut posts = posts.borrow_mut();
+        match posts.get_mut(&post_id) {
+            Some(post) if post.author == caller => {
+                let previous_mentions = std::mem::replace(&mut post.mentions, mentions.clone());
+                let previous_hashtags = std::mem::replace(&mut post.hashtags, parse_hashtags(&content));
+                post.flagged = looks_like_link_spam(&content);
+                post.content = content;
+                post.content_warning = content_warning.map(|warning| warning.trim().to_string());
+                Result::Ok((post.clone(), previous_mentions, previous_hashtags))
+            }
+            Some(_) => Result::Err("Not authorized".to_string()),
+            None => Result::Err("Post not found".to_string()),
+        }
+    });
+
+    // Only users newly mentioned by the edit are indexed and notified
+    match edited {
+        Result::Ok((post, previous_mentions, previous_hashtags)) => {
+            let removed: Vec<Principal> = previous_mentions.iter().filter(|user| !mentions.contains(user)).copied().collect();
+            let added: Vec<Principal> = mentions.iter().filter(|user| !previous_mentions.contains(user)).copied().collect();
+            unindex_mentions(post_id, &removed);
+            record_mentions(&post, &added);
+
+            // Tag indexes follow the edit the same way create_post_internal fills them
+            let removed_tags: Vec<String> = previous_hashtags.iter().filter(|tag| !post.hashtags.contains(tag)).cloned().collect();
+            let added_tags: Vec<String> = post.hashtags.iter().filter(|tag| !previous_hashtags.contains(tag)).cloned().collect();
+            if !removed_tags.is_empty() || !added_tags.is_empty() {
+                remove_from_trending_topics(caller, &removed_tags, post.created_at);
+                update_trending_topics(caller, &added_tags);
+                reduce_hashtag_cooccurrence(&previous_hashtags);
+                update_hashtag_cooccurrence(&post.hashtags);
+                reduce_content_affinity(caller, &removed_tags, 1);
+                update_content_affinity(caller, &added_tags, 1);
+            }
+            Result::Ok(post)
+        }
+        Result::Err(e) => Result::Err(e),
+    }
 }
 
 #[update]
@@ -404,14 +2145,118 @@ fn reshare_post(post_id: u64) -> Result<Post, String> {
         None => return Result::Err("Original post not found".to_string()),
     };
 
+    // Plain reshares store no content of their own; readers see the original's current text via hydrate_reshare
+    create_reshare_internal(author, &original_post, String::new())
+}
+
+#[update]
+fn quote_post(post_id: u64, commentary: String) -> Result<Post, String> {
+    let author = ic_cdk::caller();
+    if let Result::Err(e) = validate_content(&commentary, max_post_length(), "Post") {
+        return Result::Err(e);
+    }
+
+    let original_post = match POSTS.with(|posts| {
+        posts.borrow().get(&post_id).cloned()
+    }) {
+        Some(post) => post,
+        None => return Result::Err("Original post not found".to_string()),
+    };
+
+    // The commentary becomes the post content, so hashtags are parsed from it rather than the original
+    create_reshare_internal(author, &original_post, commentary)
+}
+
+// The post a reshare or quote points at, if it still exists
+fn resolve_reshare(post: &Post) -> Option<Post> {
+    match post.post_type {
+        PostType::Reshare { original_post_id, .. } => POSTS.with(|posts| posts.borrow().get(&original_post_id).cloned()),
+        _ => None,
+    }
+}
+
+// The post whose content a plain reshare shows: the first post with content down the reshare chain,
+// guarded like get_reshare_chain. None if that post is gone or any link is hidden from `viewer`.
+fn reshare_source(post: &Post, viewer: Principal) -> Option<Post> {
+    let mut visited: HashSet<u64> = HashSet::from([post.id]);
+    let mut source = resolve_reshare(post);
+    while let Some(original) = source {
+        if !visited.insert(original.id) || visited.len() > MAX_RESHARE_CHAIN_LENGTH || !is_visible_to(&original, viewer) {
+            return None;
+        }
+        if !original.content.is_empty() {
+            return Some(original);
+        }
+        source = resolve_reshare(&original);
+    }
+    None
+}
+
+fn is_plain_reshare(post: &Post) -> bool {
+    post.content.is_empty() && matches!(post.post_type, PostType::Reshare { .. })
+}
+
+// Feeds leave out plain reshares that would render empty for this viewer
+fn reshare_source_visible(post: &Post, viewer: Principal) -> bool {
+    !is_plain_reshare(post) || reshare_source(post, viewer).is_some()
+}
+
+// Fills a plain reshare's empty content from the original at read time, so edits to the original show through.
+// Quote posts keep their own commentary.
+fn hydrate_reshare(mut post: Post, viewer: Principal) -> Post {
+    if is_plain_reshare(&post) {
+        if let Some(original) = reshare_source(&post, viewer) {
+            post.content = original.content;
+        }
+    }
+    post
+}
+
+// Plain reshares collapse onto the post they reshare; quotes and originals stand on their own
+fn reshare_dedup_key(post: &Post) -> u64 {
+    match post.post_type {
+        PostType::Reshare { original_post_id, .. } if post.content.is_empty() => original_post_id,
+        _ => post.id,
+    }
+}
+
+// Keeps one entry per original, preserving the input order of the survivors. The original itself
+// wins if present; otherwise the earliest reshare does, so it's credited to whoever shared it first.
+fn dedup_reshares(posts: Vec<Post>) -> Vec<Post> {
+    let mut winners: HashMap<u64, ((bool, u64, u64), u64)> = HashMap::new();
+    for post in &posts {
+        let key = reshare_dedup_key(post);
+        let rank = (post.id != key, post.created_at, post.id);
+        let winner = winners.entry(key).or_insert((rank, post.id));
+        if rank < winner.0 {
+            *winner = (rank, post.id);
+        }
+    }
+    posts.into_iter()
+        .filter(|post| winners.get(&reshare_dedup_key(post)).is_some_and(|&(_, id)| id == post.id))
+        .collect()
+}
+
+// Helper function shared by reshare_post and quote_post
+fn create_reshare_internal(author: Principal, original_post: &Post, content: String) -> Result<Post, String> {
+    let post_id = original_post.id;
+
+    // Posts hidden from the resharer (reported, deactivated author, not in their circle) look missing
+    if !is_visible_to(original_post, author) {
+        return Result::Err("Post not found".to_string());
+    }
+    // Resharing would leak restricted content to a public audience
+    if original_post.visibility != Visibility::Public {
+        return Result::Err("Only public posts can be reshared".to_string());
+    }
+
     // Create reshare post
-    let reshare_content = format!("Reshared: {}", original_post.content);
     let post_type = PostType::Reshare {
         original_post_id: post_id,
         original_author: original_post.author,
     };
 
-    let reshare_post = match create_post_internal(author, reshare_content, post_type) {
+    let reshare_post = match create_post_internal(author, content, post_type, Vec::new(), Visibility::Public, None) {
         Result::Ok(post) => post,
         Result::Err(e) => return Result::Err(e),
     };
@@ -432,73 +2277,560 @@ fn reshare_post(post_id: u64) -> Result<Post, String> {
         created_at: time(),
         read: false,
     };
-    NOTIFICATIONS.with(|notifications| {
-        notifications.borrow_mut().insert(notification_id, notification);
-    });
+    insert_notification(notification);
 
     Result::Ok(reshare_post)
 }
 
-#[query]
-fn get_original_post(post_id: u64) -> Result<Post, String> {
-    POSTS.with(|posts| {
-        posts.borrow().get(&post_id).cloned().map(Result::Ok).unwrap_or(Result::Err("Post not found".to_string()))
-    })
+// Scheduled post functions
+#[update]
+fn schedule_post(content: String, publish_at: u64) -> Result<u64, String> {
+    let author = ic_cdk::caller();
+    let now = time();
+
+    if publish_at <= now {
+        return Result::Err("Publish time must be in the future".to_string());
+    }
+    if let Result::Err(e) = validate_content(&content, max_post_length(), "Post") {
+        return Result::Err(e);
+    }
+
+    let scheduled_id = get_next_id(&SCHEDULED_POST_COUNTER);
+    let scheduled = ScheduledPost {
+        id: scheduled_id,
+        author,
+        content,
+        publish_at,
+        created_at: now,
+    };
+
+    SCHEDULED_POSTS.with(|scheduled_posts| {
+        scheduled_posts.borrow_mut().insert(scheduled_id, scheduled);
+    });
+
+    ic_cdk_timers::set_timer(Duration::from_nanos(publish_at - now), move || {
+        publish_scheduled_post(scheduled_id);
+    });
+
+    Result::Ok(scheduled_id)
+}
+
+// Timer callback; a cancelled post is no longer in SCHEDULED_POSTS so this becomes a no-op
+fn publish_scheduled_post(scheduled_id: u64) {
+    let scheduled = SCHEDULED_POSTS.with(|scheduled_posts| scheduled_posts.borrow_mut().remove(&scheduled_id));
+    if let Some(scheduled) = scheduled {
+        let _ = create_post_internal(scheduled.author, scheduled.content, PostType::Original, Vec::new(), Visibility::Public, None);
+    }
 }
 
 #[query]
-fn get_feed(limit: u64) -> Vec<Post> {
-    POSTS.with(|posts| {
-        let mut posts_vec: Vec<Post> = posts.borrow().values().cloned().collect();
-        posts_vec.sort_by(|a, b| b.created_at.cmp(&a.created_at));
-        posts_vec.truncate(limit as usize);
+fn get_scheduled_posts() -> Vec<ScheduledPost> {
+    let caller = ic_cdk::caller();
+    SCHEDULED_POSTS.with(|scheduled_posts| {
+        let mut posts_vec: Vec<ScheduledPost> = scheduled_posts.borrow().values()
+            .filter(|scheduled| scheduled.author == caller)
+            .cloned()
+            .collect();
+        posts_vec.sort_by_key(|scheduled| scheduled.publish_at);
         posts_vec
     })
 }
 
-#[query]
-fn get_personalized_feed(limit: u64) -> Vec<Post> {
+#[update]
+fn cancel_scheduled_post(scheduled_id: u64) -> Result<(), String> {
     let caller = ic_cdk::caller();
-    let mut scored_posts: Vec<(Post, u64)> = Vec::new();
+    SCHEDULED_POSTS.with(|scheduled_posts| {
+        let mut scheduled_posts = scheduled_posts.borrow_mut();
+        match scheduled_posts.get(&scheduled_id) {
+            Some(scheduled) if scheduled.author == caller => {
+                scheduled_posts.remove(&scheduled_id);
+                Result::Ok(())
+            }
+            Some(_) => Result::Err("Not authorized".to_string()),
+            None => Result::Err("Scheduled post not found".to_string()),
+        }
+    })
+}
 
-    POSTS.with(|posts| {
-        for post in posts.borrow().values() {
-            let mut score = 0u64;
-            
-            // Score based on follows
-            if FOLLOWS.with(|follows| {
-                follows.borrow().get(&caller).map_or(false, |following| following.contains(&post.author))
-            }) {
-                score += 10;
-            }
-
-            // Score based on content affinity
-            CONTENT_AFFINITY.with(|affinity| {
-                if let Some(user_affinity) = affinity.borrow().get(&caller) {
-                    for hashtag in &post.hashtags {
-                        if let Some(hashtag_score) = user_affinity.get(hashtag) {
-                            score += hashtag_score;
-                        }
-                    }
-                }
-            });
+// Draft functions
+#[update]
+fn save_draft(content: String) -> Result<u64, String> {
+    let author = ic_cdk::caller();
+    if let Result::Err(e) = validate_content(&content, max_post_length(), "Draft") {
+        return Result::Err(e);
+    }
 
-            // Score based on interaction graph
-            INTERACTION_GRAPH.with(|graph| {
-                if let Some(interactions) = graph.borrow().get(&caller) {
-                    if let Some(strength) = interactions.get(&post.author) {
-                        score += strength;
-                    }
-                }
-            });
+    DRAFTS.with(|drafts| {
+        let mut drafts = drafts.borrow_mut();
+        let user_drafts = drafts.entry(author).or_default();
+        if user_drafts.len() >= MAX_DRAFTS_PER_USER {
+            return Result::Err(format!("You can have at most {} drafts", MAX_DRAFTS_PER_USER));
+        }
+
+        let draft_id = get_next_id(&DRAFT_COUNTER);
+        let now = time();
+        user_drafts.push(Draft {
+            id: draft_id,
+            content,
+            created_at: now,
+            updated_at: now,
+        });
+        Result::Ok(draft_id)
+    })
+}
+
+#[query]
+fn get_drafts() -> Vec<Draft> {
+    let caller = ic_cdk::caller();
+    DRAFTS.with(|drafts| {
+        let mut drafts_vec = drafts.borrow().get(&caller).cloned().unwrap_or_default();
+        drafts_vec.sort_by_key(|draft| std::cmp::Reverse(draft.updated_at));
+        drafts_vec
+    })
+}
+
+#[update]
+fn update_draft(draft_id: u64, content: String) -> Result<Draft, String> {
+    let caller = ic_cdk::caller();
+    if let Result::Err(e) = validate_content(&content, max_post_length(), "Draft") {
+        return Result::Err(e);
+    }
+
+    DRAFTS.with(|drafts| {
+        let mut drafts = drafts.borrow_mut();
+        match drafts.get_mut(&caller).and_then(|user_drafts| user_drafts.iter_mut().find(|draft| draft.id == draft_id)) {
+            Some(draft) => {
+                draft.content = content;
+                draft.updated_at = time();
+                Result::Ok(draft.clone())
+            }
+            None => Result::Err("Draft not found".to_string()),
+        }
+    })
+}
+
+// Drafts are keyed by author, so another user's draft id is simply "not found"
+fn take_draft(author: Principal, draft_id: u64) -> Option<Draft> {
+    DRAFTS.with(|drafts| {
+        let mut drafts = drafts.borrow_mut();
+        let user_drafts = drafts.get_mut(&author)?;
+        let pos = user_drafts.iter().position(|draft| draft.id == draft_id)?;
+        Some(user_drafts.remove(pos))
+    })
+}
+
+#[update]
+fn delete_draft(draft_id: u64) -> Result<(), String> {
+    let caller = ic_cdk::caller();
+    match take_draft(caller, draft_id) {
+        Some(_) => Result::Ok(()),
+        None => Result::Err("Draft not found".to_string()),
+    }
+}
+
+#[update]
+fn publish_draft(draft_id: u64) -> Result<Post, String> {
+    let caller = ic_cdk::caller();
+    let draft = DRAFTS.with(|drafts| {
+        drafts.borrow().get(&caller).and_then(|user_drafts| user_drafts.iter().find(|draft| draft.id == draft_id).cloned())
+    });
+    let draft = match draft {
+        Some(draft) => draft,
+        None => return Result::Err("Draft not found".to_string()),
+    };
+
+    // Only drop the draft once the post actually exists
+    let post = create_post(draft.content);
+    if let Result::Ok(_) = &post {
+        take_draft(caller, draft_id);
+    }
+    post
+}
+
+#[query]
+fn get_original_post(post_id: u64) -> Result<Post, String> {
+    let caller = ic_cdk::caller();
+    POSTS.with(|posts| {
+        posts.borrow().get(&post_id)
+            .filter(|post| is_visible_to(post, caller) && reshare_source_visible(post, caller))
+            .cloned()
+            .map(|post| Result::Ok(hydrate_reshare(post, caller)))
+            .unwrap_or(Result::Err("Post not found".to_string()))
+    })
+}
+
+// Breadcrumb from `post_id` back to the ultimate original, starting with the post itself.
+// Stops at the first missing or hidden link, and on a repeated id in case the data ever forms a cycle.
+#[query]
+fn get_reshare_chain(post_id: u64) -> Vec<Post> {
+    let caller = ic_cdk::caller();
+    let mut chain: Vec<Post> = Vec::new();
+    let mut visited: HashSet<u64> = HashSet::new();
+    let mut next = Some(post_id);
+
+    POSTS.with(|posts| {
+        let posts = posts.borrow();
+        while let Some(id) = next {
+            if !visited.insert(id) || chain.len() >= MAX_RESHARE_CHAIN_LENGTH {
+                break;
+            }
+            let post = match posts.get(&id).filter(|post| is_visible_to(post, caller)) {
+                Some(post) => post,
+                None => break,
+            };
+            next = match post.post_type {
+                PostType::Reshare { original_post_id, .. } => Some(original_post_id),
+                _ => None,
+            };
+            chain.push(post.clone());
+        }
+    });
+    chain.into_iter().map(|post| hydrate_reshare(post, caller)).collect()
+}
+
+// Post counterpart of get_user_profiles: input order kept, missing or hidden-from-caller ids skipped
+#[query]
+fn get_posts_by_ids(ids: Vec<u64>) -> Vec<Post> {
+    let caller = ic_cdk::caller();
+    POSTS.with(|posts| {
+        let posts = posts.borrow();
+        ids.iter()
+            .filter_map(|id| posts.get(id))
+            .filter(|post| is_visible_to(post, caller) && reshare_source_visible(post, caller))
+            .cloned()
+            .map(|post| hydrate_reshare(post, caller))
+            .collect()
+    })
+}
+
+#[query]
+fn get_feed(limit: u64) -> Vec<Post> {
+    let caller = ic_cdk::caller();
+    POSTS.with(|posts| {
+        let mut posts_vec: Vec<Post> = posts.borrow().values()
+            .filter(|post| is_visible_to(post, caller) && reshare_source_visible(post, caller) && !is_hidden_by(caller, post.id))
+            .cloned()
+            .collect();
+        posts_vec.sort_by_key(|b| std::cmp::Reverse(b.created_at));
+        posts_vec.truncate(limit as usize);
+        posts_vec.into_iter().map(|post| hydrate_reshare(post, caller)).collect()
+    })
+}
+
+#[query]
+fn get_feed_filtered(limit: u64, include_reshares: bool) -> Vec<Post> {
+    let caller = ic_cdk::caller();
+    POSTS.with(|posts| {
+        let mut posts_vec: Vec<Post> = posts.borrow().values()
+            .filter(|post| is_visible_to(post, caller) && reshare_source_visible(post, caller) && !is_hidden_by(caller, post.id))
+            .filter(|post| include_reshares || !matches!(post.post_type, PostType::Reshare { .. }))
+            .cloned()
+            .collect();
+        posts_vec.sort_by_key(|p| std::cmp::Reverse(p.created_at));
+        posts_vec.truncate(limit as usize);
+        posts_vec.into_iter().map(|post| hydrate_reshare(post, caller)).collect()
+    })
+}
+
+// Delta sync: only posts created strictly after `timestamp`, newest first
+#[query]
+fn get_feed_since(timestamp: u64, limit: u64) -> Vec<Post> {
+    let caller = ic_cdk::caller();
+    POSTS.with(|posts| {
+        let mut posts_vec: Vec<Post> = posts.borrow().values()
+            .filter(|post| post.created_at > timestamp && is_visible_to(post, caller) && reshare_source_visible(post, caller))
+            .filter(|post| !is_hidden_by(caller, post.id))
+            .cloned()
+            .collect();
+        posts_vec.sort_by_key(|p| std::cmp::Reverse(p.created_at));
+        posts_vec.truncate(limit as usize);
+        posts_vec.into_iter().map(|post| hydrate_reshare(post, caller)).collect()
+    })
+}
+
+// Posts mentioning the caller, newest first, leaving out anyone on either side of a block
+#[query]
+fn get_mentions(limit: u64) -> Vec<Post> {
+    let caller = ic_cdk::caller();
+    let post_ids = MENTIONS_INDEX.with(|index| index.borrow().get(&caller).cloned().unwrap_or_default());
+    let mut mentions: Vec<Post> = POSTS.with(|posts| {
+        let posts = posts.borrow();
+        post_ids.iter()
+            .filter_map(|id| posts.get(id))
+            .filter(|post| is_visible_to(post, caller) && !is_blocked_between(caller, post.author))
+            .cloned()
+            .collect()
+    });
+    mentions.sort_by_key(|post| std::cmp::Reverse(post.created_at));
+    mentions.truncate(limit as usize);
+    mentions
+}
+
+// Classic "following" tab: only followed authors (plus the caller if asked), newest first
+#[query]
+fn get_following_feed(limit: u64, include_own: bool) -> Vec<Post> {
+    let caller = ic_cdk::caller();
+    let following = get_following(caller);
+    POSTS.with(|posts| {
+        let mut posts_vec: Vec<Post> = posts.borrow().values()
+            .filter(|post| following.contains(&post.author) || (include_own && post.author == caller))
+            .filter(|post| is_visible_to(post, caller) && reshare_source_visible(post, caller) && !is_hidden_by(caller, post.id))
+            .cloned()
+            .collect();
+        posts_vec.sort_by_key(|p| std::cmp::Reverse(p.created_at));
+        let mut posts_vec = dedup_reshares(posts_vec);
+        posts_vec.truncate(limit as usize);
+        posts_vec.into_iter().map(|post| hydrate_reshare(post, caller)).collect()
+    })
+}
+
+// Users without a profile get the chronological feed
+#[query]
+fn get_home_feed(limit: u64) -> Vec<Post> {
+    let caller = ic_cdk::caller();
+    let preference = PROFILES.with(|profiles| profiles.borrow().get(&caller).map(|profile| profile.feed_preference.clone()));
+    match preference {
+        Some(FeedAlgorithm::Personalized) => personalized_feed_for(caller, limit, time()),
+        _ => get_feed(limit),
+    }
+}
+
+#[query]
+fn get_personalized_feed(limit: u64) -> Vec<Post> {
+    personalized_feed_for(ic_cdk::caller(), limit, time())
+}
+
+// Admin "view as" tool for diagnosing why posts rank the way they do for a given user
+#[query]
+fn preview_personalized_feed(user_id: Principal, limit: u64) -> Result<Vec<Post>, String> {
+    if !is_admin(ic_cdk::caller()) {
+        return Result::Err("Not authorized".to_string());
+    }
+    Result::Ok(personalized_feed_for(user_id, limit, time()))
+}
+
+fn personalized_score(viewer: Principal, post: &Post, now: u64) -> u64 {
+    rank_explanation(viewer, post, now).total
+}
+
+// The single source of truth for personalized ranking; `total` is the score the feed sorts by
+fn rank_explanation(viewer: Principal, post: &Post, now: u64) -> RankExplanation {
+    let follow_bonus = if is_following_user(viewer, post.author) { 10 } else { 0 };
+
+    // Small boost for verified authors
+    let verified_bonus = if is_verified(post.author) { VERIFIED_AUTHOR_BOOST } else { 0 };
+
+    let content_affinity = CONTENT_AFFINITY.with(|affinity| {
+        affinity.borrow().get(&viewer).map_or(0, |user_affinity| {
+            post.hashtags.iter().filter_map(|hashtag| user_affinity.get(hashtag)).sum()
+        })
+    });
+
+    let interaction = interaction_strength(viewer, post.author, now);
+
+    // Penalty for topics the viewer has hidden before
+    let hidden_overlap: u64 = HIDDEN_TOPICS.with(|hidden_topics| {
+        hidden_topics.borrow().get(&viewer).map_or(0, |topics| {
+            post.hashtags.iter().filter_map(|hashtag| topics.get(hashtag)).sum()
+        })
+    });
+    let hidden_topic_penalty = hidden_overlap * HIDDEN_TOPIC_PENALTY;
+
+    let score = (follow_bonus + verified_bonus + content_affinity + interaction).saturating_sub(hidden_topic_penalty);
+    let total = if post.flagged { score / FLAGGED_SCORE_DIVISOR } else { score };
+
+    RankExplanation {
+        post_id: post.id,
+        follow_bonus,
+        verified_bonus,
+        content_affinity,
+        interaction_strength: interaction,
+        hidden_topic_penalty,
+        flagged: post.flagged,
+        total,
+    }
+}
+
+#[query]
+fn explain_feed_ranking(post_id: u64) -> Result<RankExplanation, String> {
+    let caller = ic_cdk::caller();
+    match POSTS.with(|posts| posts.borrow().get(&post_id).cloned()) {
+        Some(post) if is_visible_to(&post, caller) => Result::Ok(rank_explanation(caller, &post, time())),
+        _ => Result::Err("Post not found".to_string()),
+    }
+}
+
+fn personalized_feed_for(viewer: Principal, limit: u64, now: u64) -> Vec<Post> {
+    let mut scored_posts: Vec<(Post, u64)> = Vec::new();
+    let seen = recently_seen_by(viewer);
+
+    POSTS.with(|posts| {
+        for post in posts.borrow().values() {
+            if !is_visible_to(post, viewer) || !reshare_source_visible(post, viewer) || is_hidden_by(viewer, post.id) || seen.contains(&post.id) {
+                continue;
+            }
+            scored_posts.push((post.clone(), personalized_score(viewer, post, now)));
+        }
+    });
+
+    scored_posts.sort_by_key(|b| std::cmp::Reverse(b.1));
+    let ranked = dedup_reshares(scored_posts.into_iter().map(|(post, _)| post).collect());
+    let cap = FEED_AUTHOR_CAP.with(|cap| *cap.borrow());
+    diversify_by_author(ranked, cap, limit as usize).into_iter().map(|post| hydrate_reshare(post, viewer)).collect()
+}
+
+// Takes the first `limit` posts while letting each author fill at most `cap` slots. Posts over the
+// cap are pushed below everyone else and only used when there aren't enough other posts to fill the page.
+fn diversify_by_author(ranked: Vec<Post>, cap: usize, limit: usize) -> Vec<Post> {
+    let mut per_author: HashMap<Principal, usize> = HashMap::new();
+    let mut page: Vec<Post> = Vec::new();
+    let mut overflow: Vec<Post> = Vec::new();
+    for post in ranked {
+        if page.len() >= limit {
+            break;
+        }
+        let count = per_author.entry(post.author).or_insert(0);
+        if *count < cap {
+            *count += 1;
+            page.push(post);
+        } else {
+            overflow.push(post);
+        }
+    }
+    let remaining = limit.saturating_sub(page.len());
+    page.extend(overflow.into_iter().take(remaining));
+    page
+}
+
+// Returns how many seen posts are now tracked for the caller
+#[update]
+fn mark_posts_seen(ids: Vec<u64>) -> u64 {
+    let caller = ic_cdk::caller();
+    SEEN_POSTS.with(|seen| {
+        let mut seen = seen.borrow_mut();
+        let user_seen = seen.entry(caller).or_default();
+        for id in ids {
+            if user_seen.contains(&id) {
+                continue;
+            }
+            if user_seen.len() >= MAX_SEEN_POSTS {
+                user_seen.pop_front();
+            }
+            user_seen.push_back(id);
+        }
+        user_seen.len() as u64
+    })
+}
+
+// Returns how many seen posts were forgotten
+#[update]
+fn reset_seen() -> u64 {
+    let caller = ic_cdk::caller();
+    SEEN_POSTS.with(|seen| seen.borrow_mut().remove(&caller).map_or(0, |ids| ids.len() as u64))
+}
+
+#[update]
+fn record_post_impression(post_id: u64) -> Result<u64, String> {
+    if !POSTS.with(|posts| posts.borrow().contains_key(&post_id)) {
+        return Result::Err("Post not found".to_string());
+    }
+
+    POST_IMPRESSIONS.with(|impressions| {
+        let mut impressions = impressions.borrow_mut();
+        let count = impressions.entry(post_id).or_insert(0);
+        *count += 1;
+        Result::Ok(*count)
+    })
+}
+
+#[query]
+fn get_post_analytics(post_id: u64) -> Result<PostAnalytics, String> {
+    let caller = ic_cdk::caller();
+    let post = match POSTS.with(|posts| posts.borrow().get(&post_id).cloned()) {
+        Some(post) => post,
+        None => return Result::Err("Post not found".to_string()),
+    };
+    if post.author != caller {
+        return Result::Err("Only the author can view post analytics".to_string());
+    }
+
+    Result::Ok(PostAnalytics {
+        post_id,
+        like_count: post.likes.len() as u64,
+        comment_count: post.comments.len() as u64,
+        reshare_count: post.reshare_count,
+        impression_count: POST_IMPRESSIONS.with(|impressions| impressions.borrow().get(&post_id).copied().unwrap_or(0)),
+        reactions: reaction_summary(post_id, caller).counts,
+    })
+}
 
-            scored_posts.push((post.clone(), score));
+// Likes recorded before LIKED_AT existed have no timestamp and are left out
+#[query]
+fn get_post_engagement_timeline(post_id: u64) -> Result<Vec<EngagementEvent>, String> {
+    let caller = ic_cdk::caller();
+    let post = match POSTS.with(|posts| posts.borrow().get(&post_id).cloned()) {
+        Some(post) => post,
+        None => return Result::Err("Post not found".to_string()),
+    };
+    if post.author != caller {
+        return Result::Err("Only the author can view the engagement timeline".to_string());
+    }
+
+    let mut events: Vec<EngagementEvent> = LIKED_AT.with(|liked_at| {
+        liked_at.borrow().get(&post_id).map(|likers| {
+            likers.iter()
+                .map(|(user, at)| EngagementEvent { kind: EngagementKind::Like, user_id: *user, timestamp: *at })
+                .collect()
+        }).unwrap_or_default()
+    });
+    COMMENTS.with(|comments| {
+        for comment in comments.borrow().values().filter(|comment| comment.post_id == post_id) {
+            events.push(EngagementEvent {
+                kind: EngagementKind::Comment { comment_id: comment.id },
+                user_id: comment.author,
+                timestamp: comment.created_at,
+            });
+        }
+    });
+    POSTS.with(|posts| {
+        for reshare in posts.borrow().values() {
+            if matches!(reshare.post_type, PostType::Reshare { original_post_id, .. } if original_post_id == post_id) {
+                events.push(EngagementEvent {
+                    kind: EngagementKind::Reshare { reshare_post_id: reshare.id },
+                    user_id: reshare.author,
+                    timestamp: reshare.created_at,
+                });
+            }
         }
     });
 
-    scored_posts.sort_by(|a, b| b.1.cmp(&a.1));
+    events.sort_by_key(|event| event.timestamp);
+    Result::Ok(events)
+}
+
+fn engagement_score(post: &Post) -> u64 {
+    let impressions = POST_IMPRESSIONS.with(|impressions| impressions.borrow().get(&post.id).copied().unwrap_or(0));
+    // Active engagement outweighs passive views
+    (post.likes.len() as u64) * 2
+        + (post.comments.len() as u64) * 3
+        + post.reshare_count * 4
+        + impressions / 10
+}
+
+#[query]
+fn get_trending_posts(limit: u64) -> Vec<Post> {
+    let caller = ic_cdk::caller();
+    let now = time();
+    let mut scored_posts: Vec<(Post, u64)> = POSTS.with(|posts| {
+        posts.borrow().values()
+            .filter(|post| now.saturating_sub(post.created_at) < TRENDING_POSTS_WINDOW_NS)
+            .filter(|post| is_visible_to(post, caller) && reshare_source_visible(post, caller))
+            .map(|post| (post.clone(), engagement_score(post)))
+            .collect()
+    });
+    scored_posts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| b.0.created_at.cmp(&a.0.created_at)));
     scored_posts.truncate(limit as usize);
-    scored_posts.into_iter().map(|(post, _)| post).collect()
+    scored_posts.into_iter().map(|(post, _)| hydrate_reshare(post, caller)).collect()
 }
 
 // Like/Unlike functions
@@ -511,6 +2843,9 @@ fn like_post(post_id: u64) -> Result<Post, String> {
         if let Some(post) = posts.get_mut(&post_id) {
             if !post.likes.contains(&user) {
                 post.likes.push(user);
+                LIKED_AT.with(|liked_at| {
+                    liked_at.borrow_mut().entry(post_id).or_default().insert(user, time());
+                });
                 
                 // Create notification
                 let notification_id = get_next_id(&NOTIFICATION_COUNTER);
@@ -521,12 +2856,11 @@ fn like_post(post_id: u64) -> Result<Post, String> {
                     created_at: time(),
                     read: false,
                 };
-                NOTIFICATIONS.with(|notifications| {
-                    notifications.borrow_mut().insert(notification_id, notification);
-                });
+                insert_notification(notification);
 
                 // Update interaction graph
                 update_interaction_graph(user, post.author, 1);
+                record_activity(user, ActivityAction::LikedPost { post_id });
                 
                 Result::Ok(post.clone())
             } else {
@@ -547,6 +2881,11 @@ fn unlike_post(post_id: u64) -> Result<Post, String> {
         if let Some(post) = posts.get_mut(&post_id) {
             if let Some(pos) = post.likes.iter().position(|&x| x == user) {
                 post.likes.remove(pos);
+                LIKED_AT.with(|liked_at| {
+                    if let Some(likers) = liked_at.borrow_mut().get_mut(&post_id) {
+                        likers.remove(&user);
+                    }
+                });
                 Result::Ok(post.clone())
             } else {
                 Result::Err("Post not liked".to_string())
@@ -557,54 +2896,233 @@ fn unlike_post(post_id: u64) -> Result<Post, String> {
     })
 }
 
-// Comment functions
+// Hide functions
 #[update]
-fn add_comment(post_id: u64, content: String) -> Result<Comment, String> {
-    let author = ic_cdk::caller();
-    let comment_id = get_next_id(&COMMENT_COUNTER);
-    
-    let comment = Comment {
-        id: comment_id,
-        post_id,
-        author,
-        content,
-        created_at: time(),
+fn hide_post(post_id: u64) -> Result<(), String> {
+    let caller = ic_cdk::caller();
+    let hashtags = match POSTS.with(|posts| posts.borrow().get(&post_id).map(|post| post.hashtags.clone())) {
+        Some(hashtags) => hashtags,
+        None => return Result::Err("Post not found".to_string()),
     };
 
-    COMMENTS.with(|comments| {
-        comments.borrow_mut().insert(comment_id, comment.clone());
-    });
-
-    POSTS.with(|posts| {
-        let mut posts = posts.borrow_mut();
-        if let Some(post) = posts.get_mut(&post_id) {
-            post.comments.push(comment_id);
-            
-            // Create notification
-            let notification_id = get_next_id(&NOTIFICATION_COUNTER);
-            let notification = Notification {
-                id: notification_id,
-                recipient: post.author,
-                notification_type: NotificationType::Comment { post_id, user_id: author, comment_id },
-                created_at: time(),
-                read: false,
-            };
-            NOTIFICATIONS.with(|notifications| {
-                notifications.borrow_mut().insert(notification_id, notification);
-            });
+    let newly_hidden = HIDDEN_POSTS.with(|hidden| hidden.borrow_mut().entry(caller).or_default().insert(post_id));
+    if !newly_hidden {
+        return Result::Err("Post already hidden".to_string());
+    }
 
-            // Update interaction graph
-            update_interaction_graph(author, post.author, 2);
+    reduce_content_affinity(caller, &hashtags, 1);
+    HIDDEN_TOPICS.with(|hidden_topics| {
+        let mut hidden_topics = hidden_topics.borrow_mut();
+        let user_topics = hidden_topics.entry(caller).or_default();
+        for hashtag in hashtags {
+            *user_topics.entry(hashtag).or_insert(0) += 1;
         }
     });
 
-    Result::Ok(comment)
+    Result::Ok(())
 }
 
-#[query]
+// Reaction functions
+#[update]
+fn react_to_post(post_id: u64, reaction: String) -> Result<Post, String> {
+    let user = ic_cdk::caller();
+
+    if reaction.trim().is_empty() || reaction.chars().count() > MAX_REACTION_LENGTH {
+        return Result::Err("Invalid reaction".to_string());
+    }
+
+    let post = match POSTS.with(|posts| posts.borrow().get(&post_id).cloned()) {
+        Some(post) => post,
+        None => return Result::Err("Post not found".to_string()),
+    };
+
+    let previous = POST_REACTIONS.with(|reactions| {
+        reactions.borrow_mut().entry(post_id).or_insert_with(HashMap::new).insert(user, reaction.clone())
+    });
+
+    if previous.as_ref() == Some(&reaction) {
+        return Result::Ok(post);
+    }
+
+    if post.author != user {
+        notify_reaction(post.author, post_id, user, reaction);
+    }
+    if previous.is_none() {
+        update_interaction_graph(user, post.author, 1);
+    }
+
+    Result::Ok(post)
+}
+
+fn reaction_summary(post_id: u64, viewer: Principal) -> ReactionSummary {
+    POST_REACTIONS.with(|reactions| {
+        let reactions = reactions.borrow();
+        let post_reactions = reactions.get(&post_id);
+
+        let mut tally: HashMap<&String, u64> = HashMap::new();
+        for reaction in post_reactions.into_iter().flat_map(|by_user| by_user.values()) {
+            *tally.entry(reaction).or_insert(0) += 1;
+        }
+        let mut counts: Vec<(String, u64)> = tally.into_iter().map(|(reaction, count)| (reaction.clone(), count)).collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+        ReactionSummary {
+            post_id,
+            total: counts.iter().map(|(_, count)| count).sum(),
+            counts,
+            caller_reaction: post_reactions.and_then(|by_user| by_user.get(&viewer).cloned()),
+        }
+    })
+}
+
+#[query]
+fn get_post_reaction_summary(post_id: u64) -> ReactionSummary {
+    reaction_summary(post_id, ic_cdk::caller())
+}
+
+// Changing a reaction rewrites the existing notification instead of sending another one
+fn notify_reaction(recipient: Principal, post_id: u64, user_id: Principal, reaction: String) {
+    let now = time();
+    let updated = NOTIFICATIONS.with(|notifications| {
+        let mut notifications = notifications.borrow_mut();
+        let existing = notifications.values_mut().find(|notification| {
+            notification.recipient == recipient && matches!(
+                &notification.notification_type,
+                NotificationType::Reaction { post_id: p, user_id: u, .. } if *p == post_id && *u == user_id
+            )
+        });
+        if let Some(notification) = existing {
+            notification.notification_type = NotificationType::Reaction { post_id, user_id, reaction: reaction.clone() };
+            notification.created_at = now;
+            notification.read = false;
+            true
+        } else {
+            false
+        }
+    });
+
+    if !updated {
+        let notification_id = get_next_id(&NOTIFICATION_COUNTER);
+        let notification = Notification {
+            id: notification_id,
+            recipient,
+            notification_type: NotificationType::Reaction { post_id, user_id, reaction },
+            created_at: now,
+            read: false,
+        };
+        insert_notification(notification);
+    }
+}
+
+#[update]
+fn remove_reaction(post_id: u64) -> Result<Post, String> {
+    let user = ic_cdk::caller();
+
+    let post = match POSTS.with(|posts| posts.borrow().get(&post_id).cloned()) {
+        Some(post) => post,
+        None => return Result::Err("Post not found".to_string()),
+    };
+
+    let removed = POST_REACTIONS.with(|reactions| {
+        reactions.borrow_mut().get_mut(&post_id).and_then(|post_reactions| post_reactions.remove(&user))
+    });
+
+    if removed.is_none() {
+        return Result::Err("No reaction to remove".to_string());
+    }
+    NOTIFICATIONS.with(|notifications| {
+        notifications.borrow_mut().retain(|_, notification| {
+            !matches!(
+                notification.notification_type,
+                NotificationType::Reaction { post_id: p, user_id: u, .. } if p == post_id && u == user
+            )
+        });
+    });
+    Result::Ok(post)
+}
+
+// Idempotent for optimistic UIs: the same reaction again removes it, a different one replaces it
+#[update]
+fn toggle_reaction(post_id: u64, reaction: String) -> Result<Post, String> {
+    let user = ic_cdk::caller();
+    let current = POST_REACTIONS.with(|reactions| {
+        reactions.borrow().get(&post_id).and_then(|post_reactions| post_reactions.get(&user).cloned())
+    });
+    if current.as_ref() == Some(&reaction) {
+        remove_reaction(post_id)
+    } else {
+        react_to_post(post_id, reaction)
+    }
+}
+
+// Comment functions
+#[update]
+fn add_comment(post_id: u64, content: String) -> Result<Comment, String> {
+    let author = ic_cdk::caller();
+    if let Result::Err(e) = validate_content(&content, MAX_COMMENT_LENGTH, "Comment") {
+        return Result::Err(e);
+    }
+    let content = match filter_content(content) {
+        Result::Ok(content) => content,
+        Result::Err(e) => return Result::Err(e),
+    };
+    if let Result::Err(e) = ensure_comments_open(post_id, author) {
+        return Result::Err(e);
+    }
+    if let Result::Err(e) = enforce_rate_limit(&RECENT_COMMENTS, author, time(), MAX_COMMENTS_PER_WINDOW, COMMENT_RATE_WINDOW_NS, "comments", "minute") {
+        return Result::Err(e);
+    }
+
+    let comment_id = get_next_id(&COMMENT_COUNTER);
+    
+    let comment = Comment {
+        id: comment_id,
+        post_id,
+        author,
+        content,
+        created_at: time(),
+        likes: Vec::new(),
+        parent_id: None,
+        replies: Vec::new(),
+    };
+
+    COMMENTS.with(|comments| {
+        comments.borrow_mut().insert(comment_id, comment.clone());
+    });
+    COMMENTS_BY_USER.with(|by_user| {
+        by_user.borrow_mut().entry(author).or_default().push(comment_id);
+    });
+
+    POSTS.with(|posts| {
+        let mut posts = posts.borrow_mut();
+        if let Some(post) = posts.get_mut(&post_id) {
+            post.comments.push(comment_id);
+            
+            // Create notification
+            let notification_id = get_next_id(&NOTIFICATION_COUNTER);
+            let notification = Notification {
+                id: notification_id,
+                recipient: post.author,
+                notification_type: NotificationType::Comment { post_id, user_id: author, comment_id },
+                created_at: time(),
+                read: false,
+            };
+            insert_notification(notification);
+
+            // Update interaction graph
+            update_interaction_graph(author, post.author, 2);
+            record_activity(author, ActivityAction::Commented { post_id, comment_id });
+        }
+    });
+
+    Result::Ok(comment)
+}
+
+#[query]
 fn get_comments(post_id: u64) -> Vec<Comment> {
+    let caller = ic_cdk::caller();
     POSTS.with(|posts| {
-        if let Some(post) = posts.borrow().get(&post_id) {
+        if let Some(post) = posts.borrow().get(&post_id).filter(|post| is_visible_to(post, caller)) {
             COMMENTS.with(|comments| {
                 post.comments.iter()
                     .filter_map(|&comment_id| comments.borrow().get(&comment_id).cloned())
@@ -616,17 +3134,337 @@ fn get_comments(post_id: u64) -> Vec<Comment> {
     })
 }
 
+// Replies hang off their parent comment rather than the post's top-level comment list
+#[update]
+fn add_reply(parent_comment_id: u64, content: String) -> Result<Comment, String> {
+    let author = ic_cdk::caller();
+    if let Result::Err(e) = validate_content(&content, MAX_COMMENT_LENGTH, "Reply") {
+        return Result::Err(e);
+    }
+    let content = match filter_content(content) {
+        Result::Ok(content) => content,
+        Result::Err(e) => return Result::Err(e),
+    };
+    let parent = match COMMENTS.with(|comments| comments.borrow().get(&parent_comment_id).cloned()) {
+        Some(parent) => parent,
+        None => return Result::Err("Comment not found".to_string()),
+    };
+    if let Result::Err(e) = ensure_comments_open(parent.post_id, author) {
+        return Result::Err(e);
+    }
+
+    if let Result::Err(e) = enforce_rate_limit(&RECENT_COMMENTS, author, time(), MAX_COMMENTS_PER_WINDOW, COMMENT_RATE_WINDOW_NS, "comments", "minute") {
+        return Result::Err(e);
+    }
+
+    let comment_id = get_next_id(&COMMENT_COUNTER);
+    let comment = Comment {
+        id: comment_id,
+        post_id: parent.post_id,
+        author,
+        content,
+        created_at: time(),
+        likes: Vec::new(),
+        parent_id: Some(parent_comment_id),
+        replies: Vec::new(),
+    };
+
+    COMMENTS.with(|comments| {
+        let mut comments = comments.borrow_mut();
+        comments.insert(comment_id, comment.clone());
+        if let Some(parent) = comments.get_mut(&parent_comment_id) {
+            parent.replies.push(comment_id);
+        }
+    });
+    COMMENTS_BY_USER.with(|by_user| {
+        by_user.borrow_mut().entry(author).or_default().push(comment_id);
+    });
+
+    let notification_id = get_next_id(&NOTIFICATION_COUNTER);
+    let notification = Notification {
+        id: notification_id,
+        recipient: parent.author,
+        notification_type: NotificationType::Reply { post_id: parent.post_id, comment_id, user_id: author },
+        created_at: time(),
+        read: false,
+    };
+    insert_notification(notification);
+
+    update_interaction_graph(author, parent.author, 2);
+
+    Result::Ok(comment)
+}
+
+#[query]
+fn get_reply_count(comment_id: u64) -> u64 {
+    COMMENTS.with(|comments| {
+        comments.borrow().get(&comment_id).map_or(0, |comment| comment.replies.len() as u64)
+    })
+}
+
+// Depth-first, with each level's replies oldest-first. Walks an explicit stack instead of recursing,
+// and stops expanding at MAX_THREAD_DEPTH (those comments still carry their reply ids).
+#[query]
+fn get_comment_thread(comment_id: u64) -> Result<Vec<ThreadedComment>, String> {
+    let caller = ic_cdk::caller();
+    COMMENTS.with(|comments| {
+        let comments = comments.borrow();
+        let root = match comments.get(&comment_id) {
+            Some(root) if POSTS.with(|posts| posts.borrow().get(&root.post_id).is_some_and(|post| is_visible_to(post, caller))) => root,
+            _ => return Result::Err("Comment not found".to_string()),
+        };
+
+        let mut thread = Vec::new();
+        let mut stack = vec![(root, 0u64)];
+        while let Some((comment, depth)) = stack.pop() {
+            if depth < MAX_THREAD_DEPTH {
+                let mut replies: Vec<&Comment> = comment.replies.iter()
+                    .filter_map(|reply_id| comments.get(reply_id))
+                    .collect();
+                replies.sort_by_key(|reply| (reply.created_at, reply.id));
+                // Reversed so the oldest reply is popped first
+                stack.extend(replies.into_iter().rev().map(|reply| (reply, depth + 1)));
+            }
+            thread.push(ThreadedComment { comment: comment.clone(), depth });
+        }
+        Result::Ok(thread)
+    })
+}
+
+// Profile "comments" tab; skips comments on posts the caller can't see
+#[query]
+fn get_user_comments(user_id: Principal, limit: u64) -> Vec<Comment> {
+    let caller = ic_cdk::caller();
+    let ids = COMMENTS_BY_USER.with(|by_user| by_user.borrow().get(&user_id).cloned().unwrap_or_default());
+    let mut user_comments: Vec<Comment> = COMMENTS.with(|comments| {
+        let comments = comments.borrow();
+        ids.iter().filter_map(|id| comments.get(id).cloned()).collect()
+    });
+    POSTS.with(|posts| {
+        let posts = posts.borrow();
+        user_comments.retain(|comment| posts.get(&comment.post_id).is_some_and(|post| is_visible_to(post, caller)));
+    });
+    user_comments.sort_by_key(|comment| std::cmp::Reverse(comment.created_at));
+    user_comments.truncate(limit as usize);
+    user_comments
+}
+
+#[query]
+fn get_comments_sorted(post_id: u64, by: CommentSort) -> Vec<Comment> {
+    let mut comments = get_comments(post_id);
+    match by {
+        CommentSort::Newest => comments.sort_by_key(|c| std::cmp::Reverse(c.created_at)),
+        CommentSort::Oldest => comments.sort_by_key(|c| c.created_at),
+        // Ties go to the older comment so the order is stable
+        CommentSort::Top => comments.sort_by(|a, b| b.likes.len().cmp(&a.likes.len()).then_with(|| a.created_at.cmp(&b.created_at))),
+    }
+    comments
+}
+
+#[update]
+fn like_comment(comment_id: u64) -> Result<Comment, String> {
+    let user = ic_cdk::caller();
+
+    let comment = COMMENTS.with(|comments| {
+        let mut comments = comments.borrow_mut();
+        match comments.get_mut(&comment_id) {
+            Some(comment) if comment.likes.contains(&user) => Result::Err("Comment already liked".to_string()),
+            Some(comment) => {
+                comment.likes.push(user);
+                Result::Ok(comment.clone())
+            }
+            None => Result::Err("Comment not found".to_string()),
+        }
+    });
+
+    if let Result::Ok(comment) = &comment {
+        let notification_id = get_next_id(&NOTIFICATION_COUNTER);
+        let notification = Notification {
+            id: notification_id,
+            recipient: comment.author,
+            notification_type: NotificationType::CommentLike { post_id: comment.post_id, comment_id, user_id: user },
+            created_at: time(),
+            read: false,
+        };
+        insert_notification(notification);
+
+        update_interaction_graph(user, comment.author, 1);
+    }
+
+    comment
+}
+
+#[update]
+fn unlike_comment(comment_id: u64) -> Result<Comment, String> {
+    let user = ic_cdk::caller();
+    COMMENTS.with(|comments| {
+        let mut comments = comments.borrow_mut();
+        if let Some(comment) = comments.get_mut(&comment_id) {
+            if let Some(pos) = comment.likes.iter().position(|&x| x == user) {
+                comment.likes.remove(pos);
+                Result::Ok(comment.clone())
+            } else {
+                Result::Err("Comment not liked".to_string())
+            }
+        } else {
+            Result::Err("Comment not found".to_string())
+        }
+    })
+}
+
+#[update]
+fn delete_comment(comment_id: u64) -> Result<(), String> {
+    delete_comment_as(ic_cdk::caller(), comment_id)
+}
+
+fn delete_comment_as(caller: Principal, comment_id: u64) -> Result<(), String> {
+    let comment = match COMMENTS.with(|comments| comments.borrow().get(&comment_id).cloned()) {
+        Some(comment) => comment,
+        None => return Result::Err("Comment not found".to_string()),
+    };
+
+    let post_author = POSTS.with(|posts| posts.borrow().get(&comment.post_id).map(|post| post.author));
+    if caller != comment.author && post_author != Some(caller) {
+        return Result::Err("Not authorized".to_string());
+    }
+
+    // Removing a comment takes its whole reply subtree with it
+    let removed: Vec<(u64, Principal)> = COMMENTS.with(|comments| {
+        let mut comments = comments.borrow_mut();
+        if let Some(parent) = comment.parent_id.and_then(|parent_id| comments.get_mut(&parent_id)) {
+            parent.replies.retain(|&id| id != comment_id);
+        }
+
+        let mut removed = Vec::new();
+        let mut stack = vec![comment_id];
+        while let Some(id) = stack.pop() {
+            if let Some(removed_comment) = comments.remove(&id) {
+                stack.extend(removed_comment.replies);
+                removed.push((id, removed_comment.author));
+            }
+        }
+        removed
+    });
+
+    COMMENTS_BY_USER.with(|by_user| {
+        let mut by_user = by_user.borrow_mut();
+        for (id, author) in &removed {
+            if let Some(ids) = by_user.get_mut(author) {
+                ids.retain(|comment_id| comment_id != id);
+            }
+        }
+    });
+    let removed: Vec<u64> = removed.into_iter().map(|(id, _)| id).collect();
+
+    POSTS.with(|posts| {
+        if let Some(post) = posts.borrow_mut().get_mut(&comment.post_id) {
+            post.comments.retain(|&id| id != comment_id);
+        }
+    });
+
+    // Drop the notifications that announced these comments
+    NOTIFICATIONS.with(|notifications| {
+        notifications.borrow_mut().retain(|_, notification| {
+            !matches!(
+                notification.notification_type,
+                NotificationType::Comment { comment_id: id, .. }
+                    | NotificationType::CommentLike { comment_id: id, .. }
+                    | NotificationType::CommentQuote { comment_id: id, .. }
+                    | NotificationType::Reply { comment_id: id, .. } if removed.contains(&id)
+            )
+        });
+    });
+
+    Result::Ok(())
+}
+
+// Bundles everything the post detail page needs into one call
+#[query]
+fn get_post_detail(post_id: u64) -> Result<PostDetail, String> {
+    let post = match get_original_post(post_id) {
+        Result::Ok(post) => post,
+        Result::Err(e) => return Result::Err(e),
+    };
+
+    let comments = get_comments(post_id);
+    let author_profile = PROFILES.with(|profiles| profiles.borrow().get(&post.author).cloned());
+
+    Result::Ok(PostDetail {
+        like_count: post.likes.len() as u64,
+        comment_count: comments.len() as u64,
+        reshare_count: post.reshare_count,
+        reactions: reaction_summary(post_id, ic_cdk::caller()),
+        post,
+        comments,
+        author_profile,
+    })
+}
+
+// Close friends functions
+#[update]
+fn add_close_friend(user_id: Principal) -> Result<(), String> {
+    let caller = ic_cdk::caller();
+
+    if caller == user_id {
+        return Result::Err("Cannot add yourself as a close friend".to_string());
+    }
+
+    CLOSE_FRIENDS.with(|close_friends| {
+        let mut close_friends = close_friends.borrow_mut();
+        let friends = close_friends.entry(caller).or_insert_with(Vec::new);
+        if friends.contains(&user_id) {
+            Result::Err("Already a close friend".to_string())
+        } else {
+            friends.push(user_id);
+            Result::Ok(())
+        }
+    })
+}
+
+// Takes effect immediately since visibility is checked on every read
+#[update]
+fn remove_close_friend(user_id: Principal) -> Result<(), String> {
+    let caller = ic_cdk::caller();
+    CLOSE_FRIENDS.with(|close_friends| {
+        let mut close_friends = close_friends.borrow_mut();
+        if let Some(friends) = close_friends.get_mut(&caller) {
+            if let Some(pos) = friends.iter().position(|&x| x == user_id) {
+                friends.remove(pos);
+                return Result::Ok(());
+            }
+        }
+        Result::Err("Not a close friend".to_string())
+    })
+}
+
+#[query]
+fn get_close_friends() -> Vec<Principal> {
+    let caller = ic_cdk::caller();
+    CLOSE_FRIENDS.with(|close_friends| close_friends.borrow().get(&caller).cloned().unwrap_or_default())
+}
+
 // Follow functions
 #[update]
 fn follow_user(user_id: Principal) -> Result<(), String> {
-    let follower = ic_cdk::caller();
-    
+    follow_as(ic_cdk::caller(), user_id)
+}
+
+fn follow_as(follower: Principal, user_id: Principal) -> Result<(), String> {
     if follower == user_id {
         return Result::Err("Cannot follow yourself".to_string());
     }
 
-    FOLLOWS.with(|follows| {
-        let mut follows = follows.borrow_mut();
+    if is_deactivated(follower) || is_deactivated(user_id) {
+        return Result::Err("Profile is deactivated".to_string());
+    }
+
+    let now = time();
+    if let Result::Err(e) = check_rate_limit(&RECENT_FOLLOWS, follower, now, MAX_FOLLOWS_PER_WINDOW, FOLLOW_RATE_WINDOW_NS, "follows", "hour") {
+        return Result::Err(e);
+    }
+
+    FOLLOWS.with(|follows| {
+        let mut follows = follows.borrow_mut();
         let following = follows.entry(follower).or_insert_with(Vec::new);
         if !following.contains(&user_id) {
             following.push(user_id);
@@ -642,341 +3480,2154 @@ fn follow_user(user_id: Principal) -> Result<(), String> {
                 }
             });
 
-            // Create notification
-            let notification_id = get_next_id(&NOTIFICATION_COUNTER);
-            let notification = Notification {
-                id: notification_id,
-                recipient: user_id,
-                notification_type: NotificationType::Follow { user_id: follower },
-                created_at: time(),
-                read: false,
-            };
-            NOTIFICATIONS.with(|notifications| {
-                notifications.borrow_mut().insert(notification_id, notification);
-            });
+            // Create notification
+            let notification_id = get_next_id(&NOTIFICATION_COUNTER);
+            let notification = Notification {
+                id: notification_id,
+                recipient: user_id,
+                notification_type: NotificationType::Follow { user_id: follower },
+                created_at: time(),
+                read: false,
+            };
+            insert_notification(notification);
+
+            // Update interaction graph
+            update_interaction_graph(follower, user_id, 5);
+            record_activity(follower, ActivityAction::Followed { user_id });
+            record_action(&RECENT_FOLLOWS, follower, now, MAX_FOLLOWS_PER_WINDOW);
+            
+            Result::Ok(())
+        } else {
+            Result::Err("Already following".to_string())
+        }
+    })
+}
+
+#[update]
+fn unfollow_user(user_id: Principal) -> Result<(), String> {
+    unfollow_as(ic_cdk::caller(), user_id)
+}
+
+fn unfollow_as(follower: Principal, user_id: Principal) -> Result<(), String> {
+    // Counts only include active users, so each side's own count drops only if the other side is still counted in it
+    let follower_counted = !is_deactivated(follower);
+    let followed_counted = !is_deactivated(user_id);
+    
+    FOLLOWS.with(|follows| {
+        let mut follows = follows.borrow_mut();
+        if let Some(following) = follows.get_mut(&follower) {
+            if let Some(pos) = following.iter().position(|&x| x == user_id) {
+                following.remove(pos);
+                
+                // Update profile counts
+                PROFILES.with(|profiles| {
+                    let mut profiles = profiles.borrow_mut();
+                    if followed_counted {
+                        if let Some(profile) = profiles.get_mut(&follower) {
+                            profile.following_count = profile.following_count.saturating_sub(1);
+                        }
+                    }
+                    if follower_counted {
+                        if let Some(profile) = profiles.get_mut(&user_id) {
+                            profile.followers_count = profile.followers_count.saturating_sub(1);
+                        }
+                    }
+                });
+                
+                Result::Ok(())
+            } else {
+                Result::Err("Not following".to_string())
+            }
+        } else {
+            Result::Err("Not following".to_string())
+        }
+    })
+}
+
+// Import flow: each id is followed as if by follow_user, so the follow rate limit still applies
+// part-way through a batch. Duplicate ids are reported once.
+#[update]
+fn follow_many(user_ids: Vec<Principal>) -> Vec<(Principal, Result<(), String>)> {
+    let follower = ic_cdk::caller();
+    let mut seen: HashSet<Principal> = HashSet::new();
+    user_ids.into_iter()
+        .filter(|user_id| seen.insert(*user_id))
+        .take(MAX_BULK_FOLLOW)
+        .map(|user_id| (user_id, follow_as(follower, user_id)))
+        .collect()
+}
+
+#[update]
+fn unfollow_many(user_ids: Vec<Principal>) -> Vec<(Principal, Result<(), String>)> {
+    let follower = ic_cdk::caller();
+    let mut seen: HashSet<Principal> = HashSet::new();
+    user_ids.into_iter()
+        .filter(|user_id| seen.insert(*user_id))
+        .take(MAX_BULK_FOLLOW)
+        .map(|user_id| (user_id, unfollow_as(follower, user_id)))
+        .collect()
+}
+
+#[query]
+fn get_followers(user_id: Principal) -> Vec<Principal> {
+    FOLLOWS.with(|follows| {
+        follows.borrow().iter()
+            .filter_map(|(follower, following)| {
+                if following.contains(&user_id) {
+                    Some(*follower)
+                } else {
+                    None
+                }
+            })
+            .collect()
+    })
+}
+
+#[query]
+fn get_following(user_id: Principal) -> Vec<Principal> {
+    FOLLOWS.with(|follows| {
+        follows.borrow().get(&user_id).cloned().unwrap_or_default()
+    })
+}
+
+// Resolves a page of principals to profiles, skipping users without an (active) profile
+fn profiles_page(user_ids: &[Principal], offset: u64, limit: u64) -> Vec<UserProfile> {
+    PROFILES.with(|profiles| {
+        let profiles = profiles.borrow();
+        user_ids.iter()
+            .filter_map(|user_id| profiles.get(user_id))
+            .filter(|profile| profile.active)
+            .skip(offset as usize)
+            .take(limit as usize)
+            .cloned()
+            .collect()
+    })
+}
+
+#[query]
+fn get_followers_profiles(user_id: Principal, offset: u64, limit: u64) -> Vec<UserProfile> {
+    // get_followers walks a HashMap, so sort for stable pages
+    let mut followers = get_followers(user_id);
+    followers.sort();
+    profiles_page(&followers, offset, limit)
+}
+
+#[query]
+fn get_following_profiles(user_id: Principal, offset: u64, limit: u64) -> Vec<UserProfile> {
+    profiles_page(&get_following(user_id), offset, limit)
+}
+
+#[query]
+fn is_following(user_id: Principal) -> bool {
+    is_following_user(ic_cdk::caller(), user_id)
+}
+
+#[query]
+fn does_follow_me(user_id: Principal) -> bool {
+    is_following_user(user_id, ic_cdk::caller())
+}
+
+// The caller's relationship to `user_id`: Following means the caller follows them, Follower the reverse
+#[query]
+fn get_relationship(user_id: Principal) -> Relationship {
+    let caller = ic_cdk::caller();
+    match (is_following_user(caller, user_id), is_following_user(user_id, caller)) {
+        (true, true) => Relationship::Mutual,
+        (true, false) => Relationship::Following,
+        (false, true) => Relationship::Follower,
+        (false, false) => Relationship::None,
+    }
+}
+
+// Block functions
+#[update]
+fn block_user(user_id: Principal) -> Result<(), String> {
+    let caller = ic_cdk::caller();
+
+    if caller == user_id {
+        return Result::Err("Cannot block yourself".to_string());
+    }
+
+    BLOCKS.with(|blocks| {
+        let mut blocks = blocks.borrow_mut();
+        let blocked = blocks.entry(caller).or_insert_with(Vec::new);
+        if blocked.contains(&user_id) {
+            Result::Err("User already blocked".to_string())
+        } else {
+            blocked.push(user_id);
+            Result::Ok(())
+        }
+    })
+}
+
+#[update]
+fn unblock_user(user_id: Principal) -> Result<(), String> {
+    let caller = ic_cdk::caller();
+    BLOCKS.with(|blocks| {
+        let mut blocks = blocks.borrow_mut();
+        if let Some(blocked) = blocks.get_mut(&caller) {
+            if let Some(pos) = blocked.iter().position(|&x| x == user_id) {
+                blocked.remove(pos);
+                return Result::Ok(());
+            }
+        }
+        Result::Err("User not blocked".to_string())
+    })
+}
+
+#[query]
+fn get_blocked_users() -> Vec<Principal> {
+    let caller = ic_cdk::caller();
+    BLOCKS.with(|blocks| blocks.borrow().get(&caller).cloned().unwrap_or_default())
+}
+
+// Search functions
+// 3 = exact username, 2 = username prefix, 1 = username substring, 0 = bio only, None = no match
+fn user_search_relevance(profile: &UserProfile, query_lower: &str) -> Option<u8> {
+    let username = profile.username.to_lowercase();
+    if username == query_lower {
+        Some(3)
+    } else if username.starts_with(query_lower) {
+        Some(2)
+    } else if username.contains(query_lower) {
+        Some(1)
+    } else if profile.bio.iter().any(|bio| bio.to_lowercase().contains(query_lower)) {
+        Some(0)
+    } else {
+        None
+    }
+}
+
+// Engagement (plus one, so unengaged posts still rank by age) halved for every day of age
+fn hashtag_search_score(post: &Post, now: u64) -> u64 {
+    let days_old = now.saturating_sub(post.created_at) / (24 * 60 * 60 * 1_000_000_000);
+    if days_old >= 64 {
+        0
+    } else {
+        ((engagement_score(post) + 1) * 1024) >> days_old
+    }
+}
+
+// A leading `@` switches to autocomplete mode, which only matches username prefixes
+#[query]
+fn search_users(query: String, limit: u64, offset: u64, verified_only: bool) -> Vec<UserProfile> {
+    let query_lower = query.trim().to_lowercase();
+    let (query_lower, prefix_only) = match query_lower.strip_prefix('@') {
+        Some(handle) => (handle.to_string(), true),
+        None => (query_lower, false),
+    };
+    let min_relevance = if prefix_only { 2 } else { 0 };
+
+    let mut matches: Vec<(UserProfile, u8)> = PROFILES.with(|profiles| {
+        profiles.borrow().values()
+            .filter(|profile| profile.active && (!verified_only || profile.verified))
+            .filter_map(|profile| user_search_relevance(profile, &query_lower).map(|relevance| (profile.clone(), relevance)))
+            .filter(|(_, relevance)| *relevance >= min_relevance)
+            .collect()
+    });
+    // Username as the final tie-break keeps pages stable between calls
+    matches.sort_by(|a, b| {
+        b.1.cmp(&a.1)
+            .then_with(|| b.0.followers_count.cmp(&a.0.followers_count))
+            .then_with(|| a.0.username.cmp(&b.0.username))
+    });
+    matches.into_iter()
+        .skip(offset as usize)
+        .take(limit as usize)
+        .map(|(profile, _)| profile)
+        .collect()
+}
+
+#[query]
+fn search_posts_by_hashtag(hashtag: String, limit: u64) -> Vec<Post> {
+    let caller = ic_cdk::caller();
+    let now = time();
+    let hashtag = match normalize_hashtag(&hashtag) {
+        Some(tag) => tag,
+        None => return Vec::new(),
+    };
+    let mut scored_posts: Vec<(Post, u64)> = POSTS.with(|posts| {
+        posts.borrow().values()
+            .filter(|post| post.hashtags.contains(&hashtag) && is_visible_to(post, caller))
+            .map(|post| (post.clone(), hashtag_search_score(post, now)))
+            .collect()
+    });
+    scored_posts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| b.0.created_at.cmp(&a.0.created_at)));
+    scored_posts.truncate(limit as usize);
+    scored_posts.into_iter().map(|(post, _)| hydrate_reshare(post, caller)).collect()
+}
+
+// Notification functions
+fn default_notification_preferences() -> NotificationPreferences {
+    NotificationPreferences { follows: true, likes: true, comments: true, mentions: true, reshares: true, messages: true }
+}
+
+fn notification_preferences_for(user: Principal) -> NotificationPreferences {
+    NOTIFICATION_PREFERENCES.with(|prefs| prefs.borrow().get(&user).cloned()).unwrap_or_else(default_notification_preferences)
+}
+
+fn wants_notification(prefs: &NotificationPreferences, notification_type: &NotificationType) -> bool {
+    match notification_type {
+        NotificationType::Follow { .. } => prefs.follows,
+        NotificationType::Like { .. } | NotificationType::Reaction { .. } | NotificationType::CommentLike { .. } => prefs.likes,
+        NotificationType::Comment { .. } | NotificationType::Reply { .. } => prefs.comments,
+        NotificationType::Mention { .. } | NotificationType::CommentQuote { .. } => prefs.mentions,
+        NotificationType::Reshare { .. } => prefs.reshares,
+        NotificationType::Message { .. } => prefs.messages,
+    }
+}
+
+// Every notification goes through here so the recipient's preferences are respected
+fn insert_notification(notification: Notification) {
+    if !wants_notification(&notification_preferences_for(notification.recipient), &notification.notification_type) {
+        return;
+    }
+    NOTIFICATIONS.with(|notifications| {
+        notifications.borrow_mut().insert(notification.id, notification);
+    });
+}
+
+#[query]
+fn get_notification_preferences() -> NotificationPreferences {
+    notification_preferences_for(ic_cdk::caller())
+}
+
+#[update]
+fn set_notification_preferences(prefs: NotificationPreferences) -> Result<(), String> {
+    let caller = ic_cdk::caller();
+    if !PROFILES.with(|profiles| profiles.borrow().contains_key(&caller)) {
+        return Result::Err("Profile not found".to_string());
+    }
+    NOTIFICATION_PREFERENCES.with(|all| {
+        all.borrow_mut().insert(caller, prefs);
+    });
+    Result::Ok(())
+}
+
+#[query]
+fn get_notifications() -> Vec<Notification> {
+    let caller = ic_cdk::caller();
+    NOTIFICATIONS.with(|notifications| {
+        notifications.borrow().values()
+            .filter(|notification| notification.recipient == caller)
+            .cloned()
+            .collect()
+    })
+}
+
+// Newest first by id; pass the last id of a page as `before_id` to get the next one
+#[query]
+fn get_notifications_paginated(before_id: Option<u64>, limit: u64) -> Vec<Notification> {
+    let caller = ic_cdk::caller();
+    NOTIFICATIONS.with(|notifications| {
+        let mut page: Vec<Notification> = notifications.borrow().values()
+            .filter(|notification| notification.recipient == caller)
+            .filter(|notification| before_id.is_none_or(|before| notification.id < before))
+            .cloned()
+            .collect();
+        page.sort_by_key(|n| std::cmp::Reverse(n.id));
+        page.truncate(limit as usize);
+        page
+    })
+}
+
+// Admin maintenance: drops every notification older than `max_age_secs` and returns how many were removed
+#[update]
+fn prune_notifications(max_age_secs: u64) -> Result<u64, String> {
+    if !is_admin(ic_cdk::caller()) {
+        return Result::Err("Not authorized".to_string());
+    }
+    let cutoff = time().saturating_sub(max_age_secs.saturating_mul(1_000_000_000));
+    NOTIFICATIONS.with(|notifications| {
+        let mut notifications = notifications.borrow_mut();
+        let before = notifications.len();
+        notifications.retain(|_, notification| notification.created_at >= cutoff);
+        Result::Ok((before - notifications.len()) as u64)
+    })
+}
+
+// Whether everything the notification points at still exists; follows only reference users
+fn notification_target_exists(notification_type: &NotificationType) -> bool {
+    let post_exists = |post_id: &u64| POSTS.with(|posts| posts.borrow().contains_key(post_id));
+    let comment_exists = |comment_id: &u64| COMMENTS.with(|comments| comments.borrow().contains_key(comment_id));
+    match notification_type {
+        NotificationType::Follow { .. } => true,
+        NotificationType::Like { post_id, .. }
+        | NotificationType::Mention { post_id, .. }
+        | NotificationType::Reshare { post_id, .. }
+        | NotificationType::Reaction { post_id, .. } => post_exists(post_id),
+        NotificationType::Comment { post_id, comment_id, .. }
+        | NotificationType::CommentLike { post_id, comment_id, .. }
+        | NotificationType::CommentQuote { post_id, comment_id, .. }
+        | NotificationType::Reply { post_id, comment_id, .. } => post_exists(post_id) && comment_exists(comment_id),
+        NotificationType::Message { message_id, .. } => MESSAGES.with(|messages| messages.borrow().contains_key(message_id)),
+    }
+}
+
+// Also run periodically from a timer set up in init
+fn remove_orphaned_notifications() -> u64 {
+    NOTIFICATIONS.with(|notifications| {
+        let mut notifications = notifications.borrow_mut();
+        let before = notifications.len();
+        notifications.retain(|_, notification| notification_target_exists(&notification.notification_type));
+        (before - notifications.len()) as u64
+    })
+}
+
+#[update]
+fn prune_orphaned_notifications() -> Result<u64, String> {
+    if !is_admin(ic_cdk::caller()) {
+        return Result::Err("Not authorized".to_string());
+    }
+    Result::Ok(remove_orphaned_notifications())
+}
+
+// An empty `kinds` list matches every notification type
+#[query]
+fn get_notifications_filtered(kinds: Vec<String>, unread_only: bool, limit: u64) -> Result<Vec<Notification>, String> {
+    let caller = ic_cdk::caller();
+
+    let mut wanted: Vec<&'static str> = Vec::new();
+    for kind in &kinds {
+        match NOTIFICATION_KINDS.iter().find(|known| known.eq_ignore_ascii_case(kind)) {
+            Some(known) => wanted.push(known),
+            None => return Result::Err(format!("Unknown notification type: {}", kind)),
+        }
+    }
+
+    NOTIFICATIONS.with(|notifications| {
+        let mut filtered: Vec<Notification> = notifications.borrow().values()
+            .filter(|notification| notification.recipient == caller)
+            .filter(|notification| !unread_only || !notification.read)
+            .filter(|notification| wanted.is_empty() || wanted.contains(&notification_kind(&notification.notification_type)))
+            .cloned()
+            .collect();
+        filtered.sort_by_key(|n| std::cmp::Reverse(n.created_at));
+        filtered.truncate(limit as usize);
+        Result::Ok(filtered)
+    })
+}
+
+// Newest first. Grouping is a read-time view, so the raw notifications (and their ids) are untouched.
+#[query]
+fn get_notifications_grouped() -> Vec<GroupedNotification> {
+    let mut raw = get_notifications();
+    raw.sort_by_key(|n| std::cmp::Reverse((n.created_at, n.id)));
+
+    let mut groups: Vec<GroupedNotification> = Vec::new();
+    let mut open_groups: HashMap<(&'static str, u64), usize> = HashMap::new();
+    for notification in raw {
+        let kind = notification_kind(&notification.notification_type);
+        let actor = notification_actor(&notification.notification_type);
+        let post_id = notification_group_post(&notification.notification_type);
+
+        let existing = post_id.and_then(|post_id| open_groups.get(&(kind, post_id)).copied())
+            .filter(|&index| groups[index].latest_at - notification.created_at <= NOTIFICATION_GROUP_WINDOW_NS);
+        match existing {
+            Some(index) => {
+                let group = &mut groups[index];
+                group.count += 1;
+                group.notification_ids.push(notification.id);
+                group.read = group.read && notification.read;
+                if group.recent_actors.len() < MAX_GROUP_ACTORS && !group.recent_actors.contains(&actor) {
+                    group.recent_actors.push(actor);
+                }
+            }
+            None => {
+                if let Some(post_id) = post_id {
+                    open_groups.insert((kind, post_id), groups.len());
+                }
+                groups.push(GroupedNotification {
+                    kind: kind.to_string(),
+                    post_id,
+                    count: 1,
+                    recent_actors: vec![actor],
+                    notification_ids: vec![notification.id],
+                    latest_at: notification.created_at,
+                    read: notification.read,
+                });
+            }
+        }
+    }
+    groups
+}
+
+#[update]
+fn mark_notification_as_read(notification_id: u64) -> Result<(), String> {
+    let caller = ic_cdk::caller();
+    NOTIFICATIONS.with(|notifications| {
+        let mut notifications = notifications.borrow_mut();
+        if let Some(notification) = notifications.get_mut(&notification_id) {
+            if notification.recipient == caller {
+                notification.read = true;
+                Result::Ok(())
+            } else {
+                Result::Err("Not authorized".to_string())
+            }
+        } else {
+            Result::Err("Notification not found".to_string())
+        }
+    })
+}
+
+#[update]
+fn mark_all_notifications_as_read() -> Result<(), String> {
+    let caller = ic_cdk::caller();
+    NOTIFICATIONS.with(|notifications| {
+        let mut notifications = notifications.borrow_mut();
+        for notification in notifications.values_mut() {
+            if notification.recipient == caller {
+                notification.read = true;
+            }
+        }
+        Result::Ok(())
+    })
+}
+
+// Message functions
+#[update]
+fn send_message(to_user_id: Principal, content: String) -> Result<Message, String> {
+    send_direct_message(ic_cdk::caller(), to_user_id, content, Vec::new())
+}
+
+#[update]
+fn send_message_with_attachments(to_user_id: Principal, content: String, attachments: Vec<MediaAttachment>) -> Result<Message, String> {
+    send_direct_message(ic_cdk::caller(), to_user_id, content, attachments)
+}
+
+fn send_direct_message(from_user: Principal, to_user_id: Principal, content: String, attachments: Vec<MediaAttachment>) -> Result<Message, String> {
+    if from_user == to_user_id {
+        return Result::Err("Cannot send message to yourself".to_string());
+    }
+    if has_blocked(to_user_id, from_user) {
+        return Result::Err("You cannot message this user".to_string());
+    }
+    if has_blocked(from_user, to_user_id) {
+        return Result::Err("Unblock this user to send them messages".to_string());
+    }
+    if let Result::Err(e) = validate_attachments(&attachments, MAX_MESSAGE_ATTACHMENTS) {
+        return Result::Err(e);
+    }
+    // Attachment-only messages are allowed, but any text still has to respect the limit
+    if attachments.is_empty() || !content.trim().is_empty() {
+        if let Result::Err(e) = validate_content(&content, MAX_MESSAGE_LENGTH, "Message") {
+            return Result::Err(e);
+        }
+    }
+    let content = match filter_content(content) {
+        Result::Ok(content) => content,
+        Result::Err(e) => return Result::Err(e),
+    };
+    if let Result::Err(e) = enforce_rate_limit(&RECENT_MESSAGES, from_user, time(), MAX_MESSAGES_PER_WINDOW, MESSAGE_RATE_WINDOW_NS, "messages", "minute") {
+        return Result::Err(e);
+    }
+
+    let thread_id = get_thread_id(from_user, to_user_id);
+    let message_id = get_next_id(&MESSAGE_COUNTER);
+    let message = Message {
+        id: message_id,
+        from: from_user,
+        to: to_user_id,
+        content,
+        created_at: time(),
+        read: false,
+        thread_id: thread_id.clone(),
+        status: MessageStatus::Sent,
+        attachments,
+    };
+
+    MESSAGES.with(|messages| {
+        messages.borrow_mut().insert(message_id, message.clone());
+    });
+
+    unarchive_for_participants(&thread_id, &[from_user, to_user_id]);
+
+    // Create or update chat thread
+    CHAT_THREADS.with(|threads| {
+        let mut threads = threads.borrow_mut();
+        let thread = threads.entry(thread_id.clone()).or_insert_with(|| ChatThread {
+            id: thread_id,
+            participants: vec![from_user, to_user_id],
+            last_message: None,
+            updated_at: time(),
+            name: None,
+            is_group: false,
+        });
+        thread.last_message = Some(message.clone());
+        thread.updated_at = time();
+    });
+
+    // Create notification
+    let notification_id = get_next_id(&NOTIFICATION_COUNTER);
+    let notification = Notification {
+        id: notification_id,
+        recipient: to_user_id,
+        notification_type: NotificationType::Message { user_id: from_user, message_id, has_attachments: !message.attachments.is_empty() },
+        created_at: time(),
+        read: false,
+    };
+    insert_notification(notification);
+    mark_active(from_user);
+
+    Result::Ok(message)
+}
+
+// Removes the 1:1 conversation for both participants and returns how many messages were deleted
+#[update]
+fn delete_conversation(with_user_id: Principal) -> Result<u64, String> {
+    let caller = ic_cdk::caller();
+    if caller == with_user_id {
+        return Result::Err("Cannot have a conversation with yourself".to_string());
+    }
+    let thread_id = get_thread_id(caller, with_user_id);
+
+    let removed: Vec<u64> = MESSAGES.with(|messages| {
+        let mut messages = messages.borrow_mut();
+        let ids: Vec<u64> = messages.values()
+            .filter(|message| message.thread_id == thread_id)
+            .map(|message| message.id)
+            .collect();
+        for id in &ids {
+            messages.remove(id);
+        }
+        ids
+    });
+    let had_thread = CHAT_THREADS.with(|threads| threads.borrow_mut().remove(&thread_id).is_some());
+    if removed.is_empty() && !had_thread {
+        return Result::Err("No conversation with this user".to_string());
+    }
+
+    TYPING.with(|typing| {
+        typing.borrow_mut().remove(&thread_id);
+    });
+    for flags in [&ARCHIVED_THREADS, &PINNED_THREADS] {
+        flags.with(|flags| {
+            let mut flags = flags.borrow_mut();
+            for user in [caller, with_user_id] {
+                if let Some(user_flags) = flags.get_mut(&user) {
+                    user_flags.remove(&thread_id);
+                }
+            }
+        });
+    }
+    NOTIFICATIONS.with(|notifications| {
+        notifications.borrow_mut().retain(|_, notification| {
+            !matches!(notification.notification_type, NotificationType::Message { message_id, .. } if removed.contains(&message_id))
+        });
+    });
+
+    Result::Ok(removed.len() as u64)
+}
+
+// Removes a just-sent message for everyone; the thread preview falls back to the previous message
+#[update]
+fn unsend_message(message_id: u64) -> Result<(), String> {
+    let caller = ic_cdk::caller();
+    let now = time();
+
+    let thread_id = MESSAGES.with(|messages| {
+        let mut messages = messages.borrow_mut();
+        match messages.get(&message_id) {
+            None => Result::Err("Message not found".to_string()),
+            Some(message) if message.from != caller => Result::Err("Only the sender can unsend a message".to_string()),
+            Some(message) if now.saturating_sub(message.created_at) > UNSEND_WINDOW_NS => {
+                Result::Err("Messages can only be unsent within 2 minutes of sending".to_string())
+            }
+            Some(_) => {
+                let message = messages.remove(&message_id).unwrap();
+                Result::Ok(message.thread_id)
+            }
+        }
+    });
+    let thread_id = match thread_id {
+        Result::Ok(thread_id) => thread_id,
+        Result::Err(e) => return Result::Err(e),
+    };
+
+    let previous = MESSAGES.with(|messages| {
+        messages.borrow().values()
+            .filter(|message| message.thread_id == thread_id)
+            .max_by_key(|message| (message.created_at, message.id))
+            .cloned()
+    });
+    CHAT_THREADS.with(|threads| {
+        if let Some(thread) = threads.borrow_mut().get_mut(&thread_id) {
+            if thread.last_message.as_ref().is_some_and(|last| last.id == message_id) {
+                if let Some(previous) = &previous {
+                    thread.updated_at = previous.created_at;
+                }
+                thread.last_message = previous;
+            }
+        }
+    });
+    NOTIFICATIONS.with(|notifications| {
+        notifications.borrow_mut().retain(|_, notification| {
+            !matches!(notification.notification_type, NotificationType::Message { message_id: id, .. } if id == message_id)
+        });
+    });
+
+    Result::Ok(())
+}
+
+// Group chat functions
+#[update]
+fn create_group_chat(name: String, participants: Vec<Principal>) -> Result<String, String> {
+    let creator = ic_cdk::caller();
+
+    if name.trim().is_empty() {
+        return Result::Err("Group name cannot be empty".to_string());
+    }
+
+    let mut members = vec![creator];
+    for participant in participants {
+        if !members.contains(&participant) {
+            members.push(participant);
+        }
+    }
+    if members.len() < 3 {
+        return Result::Err("A group chat needs at least two other participants".to_string());
+    }
+    if members.len() > MAX_GROUP_PARTICIPANTS {
+        return Result::Err(format!("A group chat can have at most {} participants", MAX_GROUP_PARTICIPANTS));
+    }
+    // Anonymous principals never have a profile, so this rejects them too
+    if let Some(missing) = members.iter().find(|member| !PROFILES.with(|profiles| profiles.borrow().contains_key(member))) {
+        return Result::Err(format!("User {} does not have a profile", missing));
+    }
+    // A group must not become a way around a block, whether it involves the creator or two other members
+    for (index, member) in members.iter().enumerate() {
+        if let Some(other) = members[index + 1..].iter().find(|other| is_blocked_between(*member, **other)) {
+            return if *member == creator {
+                Result::Err(format!("Cannot add {} to this group", other))
+            } else {
+                Result::Err(format!("Cannot add {} and {} to the same group", member, other))
+            };
+        }
+    }
+
+    let thread_id = format!("group_{}", get_next_id(&GROUP_CHAT_COUNTER));
+    let thread = ChatThread {
+        id: thread_id.clone(),
+        participants: members,
+        last_message: None,
+        updated_at: time(),
+        name: Some(name),
+        is_group: true,
+    };
+    CHAT_THREADS.with(|threads| {
+        threads.borrow_mut().insert(thread_id.clone(), thread);
+    });
+
+    Result::Ok(thread_id)
+}
+
+#[update]
+fn send_group_message(thread_id: String, content: String) -> Result<Message, String> {
+    let from_user = ic_cdk::caller();
+
+    let participants = match CHAT_THREADS.with(|threads| threads.borrow().get(&thread_id).map(|thread| (thread.is_group, thread.participants.clone()))) {
+        Some((true, participants)) => participants,
+        Some((false, _)) => return Result::Err("Not a group chat".to_string()),
+        None => return Result::Err("Chat thread not found".to_string()),
+    };
+    if !participants.contains(&from_user) {
+        return Result::Err("Not a participant in this chat".to_string());
+    }
+    if let Result::Err(e) = validate_content(&content, MAX_MESSAGE_LENGTH, "Message") {
+        return Result::Err(e);
+    }
+    let content = match filter_content(content) {
+        Result::Ok(content) => content,
+        Result::Err(e) => return Result::Err(e),
+    };
+    if let Result::Err(e) = enforce_rate_limit(&RECENT_MESSAGES, from_user, time(), MAX_MESSAGES_PER_WINDOW, MESSAGE_RATE_WINDOW_NS, "messages", "minute") {
+        return Result::Err(e);
+    }
+
+    let message_id = get_next_id(&MESSAGE_COUNTER);
+    let message = Message {
+        id: message_id,
+        from: from_user,
+        to: Principal::anonymous(),
+        content,
+        created_at: time(),
+        read: false,
+        thread_id: thread_id.clone(),
+        status: MessageStatus::Sent,
+        attachments: Vec::new(),
+    };
+
+    MESSAGES.with(|messages| {
+        messages.borrow_mut().insert(message_id, message.clone());
+    });
+
+    CHAT_THREADS.with(|threads| {
+        if let Some(thread) = threads.borrow_mut().get_mut(&thread_id) {
+            thread.last_message = Some(message.clone());
+            thread.updated_at = time();
+        }
+    });
+    unarchive_for_participants(&thread_id, &participants);
+
+    // Notify everyone except the sender; members blocked with the sender since the group was made get nothing
+    for recipient in participants.into_iter().filter(|participant| *participant != from_user && !is_blocked_between(from_user, *participant)) {
+        let notification_id = get_next_id(&NOTIFICATION_COUNTER);
+        let notification = Notification {
+            id: notification_id,
+            recipient,
+            notification_type: NotificationType::Message { user_id: from_user, message_id, has_attachments: false },
+            created_at: time(),
+            read: false,
+        };
+        insert_notification(notification);
+    }
+    mark_active(from_user);
+
+    Result::Ok(message)
+}
+
+// Works for both 1:1 and group threads; the caller must be a participant
+#[query]
+fn get_thread_messages(thread_id: String) -> Result<Vec<Message>, String> {
+    let caller = ic_cdk::caller();
+
+    let membership = CHAT_THREADS.with(|threads| {
+        threads.borrow().get(&thread_id).map(|thread| (thread.participants.contains(&caller), thread.is_group))
+    });
+    let is_group = match membership {
+        Some((true, is_group)) => is_group,
+        Some((false, _)) => return Result::Err("Not a participant in this chat".to_string()),
+        None => return Result::Err("Chat thread not found".to_string()),
+    };
+
+    MESSAGES.with(|messages| {
+        // Group messages from someone blocked with the caller aren't delivered to them
+        let mut thread_messages: Vec<Message> = messages.borrow().values()
+            .filter(|message| message.thread_id == thread_id)
+            .filter(|message| !is_group || message.from == caller || !is_blocked_between(caller, message.from))
+            .cloned()
+            .collect();
+        thread_messages.sort_by_key(|message| message.id);
+        Result::Ok(thread_messages.into_iter().map(|message| message_as_seen_by(caller, message)).collect())
+    })
+}
+
+#[query]
+fn get_messages(with_user_id: Principal) -> Vec<Message> {
+    let caller = ic_cdk::caller();
+    MESSAGES.with(|messages| {
+        messages.borrow().values()
+            .filter(|message| {
+                (message.from == caller && message.to == with_user_id) ||
+                (message.from == with_user_id && message.to == caller)
+            })
+            .map(|message| message_as_seen_by(caller, message.clone()))
+            .collect()
+    })
+}
+
+#[query]
+fn search_messages(with_user_id: Principal, query: String, limit: u64) -> Vec<Message> {
+    let caller = ic_cdk::caller();
+    let query = query.trim().to_lowercase();
+    if query.is_empty() {
+        return Vec::new();
+    }
+    let mut matches: Vec<Message> = MESSAGES.with(|messages| {
+        messages.borrow().values()
+            .filter(|message| {
+                (message.from == caller && message.to == with_user_id) ||
+                (message.from == with_user_id && message.to == caller)
+            })
+            .filter(|message| message.content.to_lowercase().contains(&query))
+            .map(|message| message_as_seen_by(caller, message.clone()))
+            .collect()
+    });
+    matches.sort_by_key(|message| std::cmp::Reverse((message.created_at, message.id)));
+    matches.truncate(limit as usize);
+    matches
+}
+
+#[query]
+fn get_new_messages(with_user_id: Principal, since: u64) -> MessageSync {
+    let caller = ic_cdk::caller();
+    let mut new_messages: Vec<Message> = MESSAGES.with(|messages| {
+        messages.borrow().values()
+            .filter(|message| {
+                (message.from == caller && message.to == with_user_id) ||
+                (message.from == with_user_id && message.to == caller)
+            })
+            .filter(|message| message.created_at > since)
+            .map(|message| message_as_seen_by(caller, message.clone()))
+            .collect()
+    });
+    new_messages.sort_by_key(|message| (message.created_at, message.id));
+    MessageSync { messages: new_messages, server_time: time() }
+}
+
+// Returns up to `limit` messages older than the cursor, in ascending order for display.
+// Message ids are allocated sequentially, so they double as a stable cursor.
+#[query]
+fn get_messages_paginated(with_user_id: Principal, before_message_id: Option<u64>, limit: u64) -> Vec<Message> {
+    let caller = ic_cdk::caller();
+    MESSAGES.with(|messages| {
+        let mut page: Vec<Message> = messages.borrow().values()
+            .filter(|message| {
+                (message.from == caller && message.to == with_user_id) ||
+                (message.from == with_user_id && message.to == caller)
+            })
+            .filter(|message| before_message_id.is_none_or(|before| message.id < before))
+            .map(|message| message_as_seen_by(caller, message.clone()))
+            .collect();
+        page.sort_by_key(|m| std::cmp::Reverse(m.id));
+        page.truncate(limit as usize);
+        page.reverse();
+        page
+    })
+}
+
+// All is the inbox: everything not archived, pinned threads first
+#[query]
+fn get_chat_threads(filter: ThreadFilter) -> Vec<ChatThread> {
+    let caller = ic_cdk::caller();
+    let archived = ARCHIVED_THREADS.with(|archived| archived.borrow().get(&caller).cloned().unwrap_or_default());
+    let pinned = PINNED_THREADS.with(|pinned| pinned.borrow().get(&caller).cloned().unwrap_or_default());
+    CHAT_THREADS.with(|threads| {
+        let mut caller_threads: Vec<ChatThread> = threads.borrow().values()
+            .filter(|thread| thread.participants.contains(&caller))
+            .filter(|thread| match filter {
+                ThreadFilter::All => !archived.contains(&thread.id),
+                ThreadFilter::Archived => archived.contains(&thread.id),
+                ThreadFilter::Pinned => pinned.contains(&thread.id),
+            })
+            .map(|thread| {
+                let mut thread = thread.clone();
+                thread.last_message = thread.last_message.map(|message| message_as_seen_by(caller, message));
+                thread
+            })
+            .collect();
+        caller_threads.sort_by_key(|thread| (!pinned.contains(&thread.id), std::cmp::Reverse(thread.updated_at)));
+        caller_threads
+    })
+}
+
+fn is_thread_participant(thread_id: &str, user: Principal) -> bool {
+    CHAT_THREADS.with(|threads| threads.borrow().get(thread_id).is_some_and(|thread| thread.participants.contains(&user)))
+}
+
+// Adds or removes `thread_id` in the caller's set; errors if it was already in the requested state
+fn set_thread_flag(flags: &'static std::thread::LocalKey<RefCell<HashMap<Principal, HashSet<String>>>>, thread_id: String, on: bool, already: &str) -> Result<(), String> {
+    let caller = ic_cdk::caller();
+    if !is_thread_participant(&thread_id, caller) {
+        return Result::Err("Chat thread not found".to_string());
+    }
+    let changed = flags.with(|flags| {
+        let mut flags = flags.borrow_mut();
+        let user_flags = flags.entry(caller).or_default();
+        if on { user_flags.insert(thread_id) } else { user_flags.remove(&thread_id) }
+    });
+    if changed {
+        Result::Ok(())
+    } else {
+        Result::Err(already.to_string())
+    }
+}
+
+#[update]
+fn archive_thread(thread_id: String) -> Result<(), String> {
+    set_thread_flag(&ARCHIVED_THREADS, thread_id, true, "Thread already archived")
+}
+
+#[update]
+fn unarchive_thread(thread_id: String) -> Result<(), String> {
+    set_thread_flag(&ARCHIVED_THREADS, thread_id, false, "Thread not archived")
+}
+
+#[update]
+fn pin_thread(thread_id: String) -> Result<(), String> {
+    set_thread_flag(&PINNED_THREADS, thread_id, true, "Thread already pinned")
+}
+
+#[update]
+fn unpin_thread(thread_id: String) -> Result<(), String> {
+    set_thread_flag(&PINNED_THREADS, thread_id, false, "Thread not pinned")
+}
+
+// A new message brings the thread back into everyone's inbox
+fn unarchive_for_participants(thread_id: &str, participants: &[Principal]) {
+    ARCHIVED_THREADS.with(|archived| {
+        let mut archived = archived.borrow_mut();
+        for participant in participants {
+            if let Some(user_archived) = archived.get_mut(participant) {
+                user_archived.remove(thread_id);
+            }
+        }
+    });
+}
+
+fn message_preview(message: &Message) -> String {
+    let content = message.content.trim();
+    let text = if content.chars().count() <= MESSAGE_PREVIEW_LENGTH {
+        content.to_string()
+    } else {
+        let truncated: String = content.chars().take(MESSAGE_PREVIEW_LENGTH).collect();
+        format!("{}…", truncated)
+    };
+    match (message.attachments.len(), text.is_empty()) {
+        (0, _) => text,
+        (1, true) => "📎 Attachment".to_string(),
+        (count, true) => format!("📎 {} attachments", count),
+        (_, false) => format!("📎 {}", text),
+    }
+}
+
+// Inbox rows (pinned first, then newest; archived left out) with everything needed to render without extra lookups
+#[query]
+fn get_conversation_list() -> Vec<ConversationPreview> {
+    let caller = ic_cdk::caller();
+    get_chat_threads(ThreadFilter::All).into_iter()
+        .map(|thread| {
+            let (other_participant, other_username) = if thread.is_group {
+                (Principal::anonymous(), thread.name.clone())
+            } else {
+                let other_participant = thread.participants.iter()
+                    .copied()
+                    .find(|participant| *participant != caller)
+                    .unwrap_or(caller);
+                let other_username = PROFILES.with(|profiles| {
+                    profiles.borrow().get(&other_participant).map(|profile| profile.username.clone())
+                });
+                (other_participant, other_username)
+            };
+            ConversationPreview {
+                thread_id: thread.id,
+                is_group: thread.is_group,
+                other_participant,
+                other_username,
+                last_message_preview: thread.last_message.as_ref().map(message_preview),
+                last_message_from: thread.last_message.as_ref().map(|message| message.from),
+                updated_at: thread.updated_at,
+            }
+        })
+        .collect()
+}
+
+fn group_read_up_to(thread_id: &str, user: Principal) -> u64 {
+    GROUP_READ_UP_TO.with(|read| read.borrow().get(thread_id).and_then(|members| members.get(&user)).copied().unwrap_or(0))
+}
+
+// Unread counts keyed by thread id, computed in a single pass over all messages.
+// 1:1 messages use their `read` flag; group messages count if they're newer than the user's read cursor.
+fn unread_counts_by_thread(user: Principal) -> HashMap<String, u64> {
+    let group_cursors: HashMap<String, u64> = CHAT_THREADS.with(|threads| {
+        threads.borrow().values()
+            .filter(|thread| thread.is_group && thread.participants.contains(&user))
+            .map(|thread| (thread.id.clone(), group_read_up_to(&thread.id, user)))
+            .collect()
+    });
+    MESSAGES.with(|messages| {
+        let mut counts = HashMap::new();
+        for message in messages.borrow().values() {
+            let unread = match group_cursors.get(&message.thread_id) {
+                Some(read_up_to) => message.from != user && message.id > *read_up_to && !is_blocked_between(user, message.from),
+                None => message.to == user && !message.read,
+            };
+            if unread {
+                *counts.entry(message.thread_id.clone()).or_insert(0) += 1;
+            }
+        }
+        counts
+    })
+}
+
+// Moves the user's cursor to the thread's newest message and returns how many messages that marked read
+fn mark_group_read(thread_id: &str, user: Principal) -> u64 {
+    let read_up_to = group_read_up_to(thread_id, user);
+    let (newest, count) = MESSAGES.with(|messages| {
+        let messages = messages.borrow();
+        let in_thread = messages.values().filter(|message| message.thread_id == thread_id);
+        let newest = in_thread.clone().map(|message| message.id).max().unwrap_or(0);
+        let count = in_thread
+            .filter(|message| message.from != user && message.id > read_up_to && !is_blocked_between(user, message.from))
+            .count() as u64;
+        (newest, count)
+    });
+    if newest > read_up_to {
+        GROUP_READ_UP_TO.with(|read| {
+            read.borrow_mut().entry(thread_id.to_string()).or_default().insert(user, newest);
+        });
+    }
+    count
+}
+
+#[update]
+fn mark_group_messages_as_read(thread_id: String) -> Result<u64, String> {
+    let caller = ic_cdk::caller();
+    match CHAT_THREADS.with(|threads| threads.borrow().get(&thread_id).map(|thread| (thread.is_group, thread.participants.contains(&caller)))) {
+        Some((true, true)) => Result::Ok(mark_group_read(&thread_id, caller)),
+        Some((false, _)) => Result::Err("Not a group chat".to_string()),
+        Some((true, false)) => Result::Err("Not a participant in this chat".to_string()),
+        None => Result::Err("Chat thread not found".to_string()),
+    }
+}
+
+#[query]
+fn get_chat_threads_with_unread() -> Vec<ChatThreadSummary> {
+    let caller = ic_cdk::caller();
+    let unread_by_thread = unread_counts_by_thread(caller);
+
+    CHAT_THREADS.with(|threads| {
+        let mut summaries: Vec<ChatThreadSummary> = threads.borrow().values()
+            .filter(|thread| thread.participants.contains(&caller))
+            .map(|thread| {
+                let other_participant = thread.participants.iter()
+                    .copied()
+                    .find(|participant| *participant != caller)
+                    .unwrap_or(caller);
+                let unread_count = unread_by_thread.get(&thread.id).copied().unwrap_or(0);
+                let mut thread = thread.clone();
+                thread.last_message = thread.last_message.map(|message| message_as_seen_by(caller, message));
+                ChatThreadSummary { thread, other_participant, unread_count }
+            })
+            .collect();
+        summaries.sort_by_key(|summary| std::cmp::Reverse(summary.thread.updated_at));
+        summaries
+    })
+}
+
+fn read_receipts_enabled_for(user: Principal) -> bool {
+    !READ_RECEIPTS_DISABLED.with(|disabled| disabled.borrow().contains(&user))
+}
+
+// Receipts are reciprocal: a sender only sees Read when both they and the reader share receipts.
+// Otherwise the sender gets Delivered, while the reader's own copy keeps the real state for unread counts.
+fn message_as_seen_by(viewer: Principal, mut message: Message) -> Message {
+    let hide_read = message.from == viewer
+        && message.status == MessageStatus::Read
+        && !(read_receipts_enabled_for(message.from) && read_receipts_enabled_for(message.to));
+    if hide_read {
+        message.status = MessageStatus::Delivered;
+        message.read = false;
+    }
+    message
+}
+
+#[query]
+fn get_read_receipts_enabled() -> bool {
+    read_receipts_enabled_for(ic_cdk::caller())
+}
+
+#[update]
+fn set_read_receipts_enabled(enabled: bool) -> Result<(), String> {
+    let caller = ic_cdk::caller();
+    if !PROFILES.with(|profiles| profiles.borrow().contains_key(&caller)) {
+        return Result::Err("Profile not found".to_string());
+    }
+    READ_RECEIPTS_DISABLED.with(|disabled| {
+        let mut disabled = disabled.borrow_mut();
+        if enabled { disabled.remove(&caller) } else { disabled.insert(caller) }
+    });
+    Result::Ok(())
+}
+
+// Message queries can't persist state, so clients call this right after fetching a conversation.
+// Only Sent messages move forward; Read messages are never downgraded.
+#[update]
+fn mark_messages_as_delivered(from_user_id: Principal) -> u64 {
+    let caller = ic_cdk::caller();
+    let mut count = 0u64;
+
+    MESSAGES.with(|messages| {
+        let mut messages = messages.borrow_mut();
+        for message in messages.values_mut() {
+            if message.from == from_user_id && message.to == caller && message.status == MessageStatus::Sent {
+                message.status = MessageStatus::Delivered;
+                count += 1;
+            }
+        }
+    });
+
+    count
+}
+
+#[update]
+fn mark_messages_as_read(from_user_id: Principal) -> u64 {
+    let caller = ic_cdk::caller();
+    let mut count = 0u64;
+    
+    MESSAGES.with(|messages| {
+        let mut messages = messages.borrow_mut();
+        for message in messages.values_mut() {
+            if message.from == from_user_id && message.to == caller && !message.read {
+                message.read = true;
+                message.status = MessageStatus::Read;
+                count += 1;
+            }
+        }
+    });
+    
+    count
+}
+
+#[update]
+fn mark_all_messages_as_read() -> u64 {
+    let caller = ic_cdk::caller();
+    let mut count = 0u64;
+
+    MESSAGES.with(|messages| {
+        let mut messages = messages.borrow_mut();
+        for message in messages.values_mut() {
+            if message.to == caller && !message.read {
+                message.read = true;
+                message.status = MessageStatus::Read;
+                count += 1;
+            }
+        }
+    });
+
+    let group_threads: Vec<String> = CHAT_THREADS.with(|threads| {
+        threads.borrow().values()
+            .filter(|thread| thread.is_group && thread.participants.contains(&caller))
+            .map(|thread| thread.id.clone())
+            .collect()
+    });
+    for thread_id in group_threads {
+        count += mark_group_read(&thread_id, caller);
+    }
+
+    count
+}
+
+// Presence functions
+#[update]
+fn set_typing(to_user_id: Principal, is_typing: bool) -> Result<(), String> {
+    let caller = ic_cdk::caller();
+
+    if caller == to_user_id {
+        return Result::Err("Cannot chat with yourself".to_string());
+    }
+
+    let thread_id = get_thread_id(caller, to_user_id);
+    TYPING.with(|typing| {
+        let mut typing = typing.borrow_mut();
+        if is_typing {
+            typing.entry(thread_id).or_insert_with(HashMap::new).insert(caller, time());
+        } else if let Some(thread_typing) = typing.get_mut(&thread_id) {
+            thread_typing.remove(&caller);
+            if thread_typing.is_empty() {
+                typing.remove(&thread_id);
+            }
+        }
+    });
+
+    Result::Ok(())
+}
+
+// Whether `with_user_id` is currently typing in their thread with the caller
+#[query]
+fn get_typing_status(with_user_id: Principal) -> bool {
+    let caller = ic_cdk::caller();
+    let thread_id = get_thread_id(caller, with_user_id);
+    let now = time();
+    TYPING.with(|typing| {
+        typing.borrow()
+            .get(&thread_id)
+            .and_then(|thread_typing| thread_typing.get(&with_user_id))
+            .is_some_and(|&started| now.saturating_sub(started) < TYPING_TIMEOUT_NS)
+    })
+}
+
+#[update]
+fn update_last_seen() -> u64 {
+    let caller = ic_cdk::caller();
+    let now = time();
+    LAST_SEEN.with(|last_seen| {
+        last_seen.borrow_mut().insert(caller, now);
+    });
+    now
+}
+
+// Returns 0 if the user has never reported presence
+#[query]
+fn get_last_seen(user_id: Principal) -> u64 {
+    LAST_SEEN.with(|last_seen| last_seen.borrow().get(&user_id).copied().unwrap_or(0))
+}
+
+// Activity functions
+#[query]
+fn get_following_activity(limit: u64) -> Vec<ActivityItem> {
+    following_activity_for(ic_cdk::caller(), limit)
+}
+
+// Entries about posts the viewer can't see (or that are gone) are skipped, as are deactivated and blocked actors
+fn following_activity_for(viewer: Principal, limit: u64) -> Vec<ActivityItem> {
+    let following = FOLLOWS.with(|follows| follows.borrow().get(&viewer).cloned().unwrap_or_default());
+    let post_visible = |post_id: u64| POSTS.with(|posts| posts.borrow().get(&post_id).is_some_and(|post| is_visible_to(post, viewer)));
+
+    ACTIVITY_LOG.with(|log| {
+        // New entries go on the back, so walking it backwards yields newest first
+        log.borrow().iter()
+            .rev()
+            .filter(|activity| following.contains(&activity.actor))
+            .filter(|activity| !is_deactivated(activity.actor) && !is_blocked_between(viewer, activity.actor))
+            .filter(|activity| match activity.action {
+                ActivityAction::LikedPost { post_id } | ActivityAction::Commented { post_id, .. } => post_visible(post_id),
+                ActivityAction::Followed { .. } => true,
+            })
+            .take(limit as usize)
+            .cloned()
+            .collect()
+    })
+}
+
+// Social graph functions
+#[query]
+fn get_mutual_connections(user_id: Principal) -> Vec<Principal> {
+    let caller = ic_cdk::caller();
+    let caller_following = FOLLOWS.with(|follows| {
+        follows.borrow().get(&caller).cloned().unwrap_or_default()
+    });
+    let user_following = FOLLOWS.with(|follows| {
+        follows.borrow().get(&user_id).cloned().unwrap_or_default()
+    });
+    
+    caller_following.into_iter()
+        .filter(|user| user_following.contains(user))
+        .collect()
+}
+
+// People who follow both `a` and `b`
+fn common_followers(a: Principal, b: Principal) -> Vec<Principal> {
+    FOLLOWS.with(|follows| {
+        follows.borrow().iter()
+            .filter(|(follower, following)| **follower != a && **follower != b && following.contains(&a) && following.contains(&b))
+            .map(|(follower, _)| *follower)
+            .collect()
+    })
+}
+
+#[query]
+fn get_common_followers(user_id: Principal) -> Vec<Principal> {
+    let caller = ic_cdk::caller();
+    common_followers(caller, user_id).into_iter()
+        .filter(|follower| !is_blocked_between(caller, *follower))
+        .collect()
+}
+
+// Profile-card variant of get_common_followers for "followed by people you know"
+#[query]
+fn get_common_follower_profiles(user_id: Principal) -> Vec<UserProfile> {
+    let caller = ic_cdk::caller();
+    let followers = common_followers(caller, user_id);
+    PROFILES.with(|profiles| {
+        let profiles = profiles.borrow();
+        followers.iter()
+            .filter(|follower| !is_blocked_between(caller, **follower))
+            .filter_map(|follower| profiles.get(follower))
+            .filter(|profile| profile.active)
+            .cloned()
+            .collect()
+    })
+}
+
+// `weight` scaled down linearly over ACTIVE_USERS_WINDOW_NS; zero once outside the window
+fn recency_weighted(weight: u64, at: u64, now: u64) -> u64 {
+    let age = now.saturating_sub(at);
+    if age >= ACTIVE_USERS_WINDOW_NS {
+        return 0;
+    }
+    weight * 100 * (ACTIVE_USERS_WINDOW_NS - age) / ACTIVE_USERS_WINDOW_NS
+}
+
+#[query]
+fn get_active_users(limit: u64) -> Vec<UserProfile> {
+    let caller = ic_cdk::caller();
+    let now = time();
+    let cutoff = now.saturating_sub(ACTIVE_USERS_WINDOW_NS);
+
+    let mut scores: HashMap<Principal, u64> = LAST_ACTIVE.with(|last_active| {
+        last_active.borrow().iter()
+            .filter(|(user, at)| **at >= cutoff && **user != caller && !is_blocked_between(caller, **user))
+            .map(|(user, _)| (*user, 0))
+            .collect()
+    });
+
+    POSTS.with(|posts| {
+        for post in posts.borrow().values() {
+            if let Some(score) = scores.get_mut(&post.author) {
+                *score += recency_weighted(ACTIVE_POST_WEIGHT, post.created_at, now);
+            }
+        }
+    });
+    COMMENTS.with(|comments| {
+        for comment in comments.borrow().values() {
+            if let Some(score) = scores.get_mut(&comment.author) {
+                *score += recency_weighted(ACTIVE_COMMENT_WEIGHT, comment.created_at, now);
+            }
+        }
+    });
+    ACTIVITY_LOG.with(|log| {
+        for item in log.borrow().iter().filter(|item| matches!(item.action, ActivityAction::LikedPost { .. })) {
+            if let Some(score) = scores.get_mut(&item.actor) {
+                *score += recency_weighted(ACTIVE_LIKE_WEIGHT, item.created_at, now);
+            }
+        }
+    });
+
+    let last_active_at = |user: &Principal| LAST_ACTIVE.with(|last_active| last_active.borrow().get(user).copied().unwrap_or(0));
+    let mut ranked: Vec<(UserProfile, u64)> = PROFILES.with(|profiles| {
+        let profiles = profiles.borrow();
+        scores.iter()
+            .filter_map(|(user, score)| profiles.get(user).filter(|profile| profile.active).map(|profile| (profile.clone(), *score)))
+            .collect()
+    });
+    ranked.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| last_active_at(&b.0.id).cmp(&last_active_at(&a.0.id))));
+    ranked.truncate(limit as usize);
+    ranked.into_iter().map(|(profile, _)| profile).collect()
+}
+
+#[query]
+fn suggest_connections(limit: u64) -> Vec<UserProfile> {
+    connection_suggestions(ic_cdk::caller(), limit, time())
+}
 
-            // Update interaction graph
-            update_interaction_graph(follower, user_id, 5);
-            
-            Result::Ok(())
-        } else {
-            Result::Err("Already following".to_string())
+// Friends of friends: candidate -> how many of the caller's followees follow them
+fn second_degree_counts(caller: Principal, caller_following: &[Principal]) -> HashMap<Principal, u64> {
+    let mut counts: HashMap<Principal, u64> = HashMap::new();
+    FOLLOWS.with(|follows| {
+        let follows = follows.borrow();
+        for followee in caller_following {
+            for candidate in follows.get(followee).into_iter().flatten() {
+                if *candidate != caller {
+                    *counts.entry(*candidate).or_insert(0) += 1;
+                }
+            }
         }
-    })
+    });
+    counts
 }
 
-#[update]
-fn unfollow_user(user_id: Principal) -> Result<(), String> {
-    let follower = ic_cdk::caller();
+fn connection_suggestions(caller: Principal, limit: u64, now: u64) -> Vec<UserProfile> {
+    let caller_following = FOLLOWS.with(|follows| {
+        follows.borrow().get(&caller).cloned().unwrap_or_default()
+    });
+    let second_degree = second_degree_counts(caller, &caller_following);
     
-    FOLLOWS.with(|follows| {
-        let mut follows = follows.borrow_mut();
-        if let Some(following) = follows.get_mut(&follower) {
-            if let Some(pos) = following.iter().position(|&x| x == user_id) {
-                following.remove(pos);
+    let mut suggestions: Vec<(UserProfile, u64)> = Vec::new();
+    
+    PROFILES.with(|profiles| {
+        for profile in profiles.borrow().values() {
+            if profile.id != caller
+                && profile.active
+                && !caller_following.contains(&profile.id)
+                && !is_blocked_between(caller, profile.id)
+            {
+                let mut score = 0u64;
                 
-                // Update profile counts
-                PROFILES.with(|profiles| {
-                    let mut profiles = profiles.borrow_mut();
-                    if let Some(profile) = profiles.get_mut(&follower) {
-                        profile.following_count = profile.following_count.saturating_sub(1);
-                    }
-                    if let Some(profile) = profiles.get_mut(&user_id) {
-                        profile.followers_count = profile.followers_count.saturating_sub(1);
+                // Score based on mutual connections
+                let mutual_count = FOLLOWS.with(|follows| {
+                    follows.borrow().get(&profile.id).map_or(0, |following| {
+                        following.iter().filter(|user| caller_following.contains(user)).count() as u64
+                    })
+                });
+                score += mutual_count * 10;
+
+                // Score based on people the caller follows who follow this user
+                score += second_degree.get(&profile.id).copied().unwrap_or(0) * SECOND_DEGREE_WEIGHT;
+                
+                // Score based on content affinity
+                CONTENT_AFFINITY.with(|affinity| {
+                    if let Some(caller_affinity) = affinity.borrow().get(&caller) {
+                        if let Some(profile_affinity) = affinity.borrow().get(&profile.id) {
+                            for (hashtag, caller_score) in caller_affinity {
+                                if let Some(profile_score) = profile_affinity.get(hashtag) {
+                                    score += caller_score.min(profile_score);
+                                }
+                            }
+                        }
                     }
                 });
+
+                // Score based on recent interactions with this user
+                score += interaction_strength(caller, profile.id, now);
                 
-                Result::Ok(())
-            } else {
-                Result::Err("Not following".to_string())
+                suggestions.push((profile.clone(), score));
             }
-        } else {
-            Result::Err("Not following".to_string())
         }
-    })
+    });
+    
+    suggestions.sort_by_key(|b| std::cmp::Reverse(b.1));
+    suggestions.truncate(limit as usize);
+    suggestions.into_iter().map(|(profile, _)| profile).collect()
 }
 
+// Strength on a 0-100 scale relative to the caller's strongest connection, so values are comparable across users
 #[query]
-fn get_followers(user_id: Principal) -> Vec<Principal> {
-    FOLLOWS.with(|follows| {
-        follows.borrow().iter()
-            .filter_map(|(follower, following)| {
-                if following.contains(&user_id) {
-                    Some(*follower)
-                } else {
-                    None
-                }
+fn get_connection_strength(user_id: Principal) -> u64 {
+    let caller = ic_cdk::caller();
+    let now = time();
+
+    let max_strength = INTERACTION_GRAPH.with(|graph| {
+        graph.borrow()
+            .get(&caller)
+            .and_then(|interactions| interactions.values().map(|edge| decayed_weight(edge, now)).max())
+            .unwrap_or(0)
+    });
+    if max_strength == 0 {
+        return 0;
+    }
+
+    let strength = interaction_strength(caller, user_id, now);
+    ((strength as u128 * 100) / max_strength as u128) as u64
+}
+
+#[query]
+fn get_top_connections(limit: u64) -> Vec<(Principal, u64)> {
+    let caller = ic_cdk::caller();
+    let now = time();
+    let mut connections: Vec<(Principal, u64)> = INTERACTION_GRAPH.with(|graph| {
+        graph.borrow()
+            .get(&caller)
+            .map(|interactions| {
+                interactions.iter()
+                    .map(|(user, edge)| (*user, decayed_weight(edge, now)))
+                    .filter(|(_, weight)| *weight > 0)
+                    .collect()
             })
-            .collect()
-    })
+            .unwrap_or_default()
+    });
+    connections.sort_by_key(|c| std::cmp::Reverse(c.1));
+    connections.truncate(limit as usize);
+    connections
 }
 
+// Trending topics
 #[query]
-fn get_following(user_id: Principal) -> Vec<Principal> {
-    FOLLOWS.with(|follows| {
-        follows.borrow().get(&user_id).cloned().unwrap_or_default()
+fn get_trending_topics(limit: u64) -> Vec<TrendingTopic> {
+    let mut topics_vec: Vec<TrendingTopic> = TRENDING_TOPICS.with(|topics| topics.borrow().values().cloned().collect());
+    sort_by_trending_score(&mut topics_vec, time());
+    topics_vec.truncate(limit as usize);
+    topics_vec
+}
+
+// Explore-page pagination. Ordering ignores recency so pages don't shift between requests:
+// highest count first, with the tag name breaking ties.
+#[query]
+fn get_trending_topics_page(offset: u64, limit: u64) -> Vec<TrendingTopic> {
+    let mut topics_vec: Vec<TrendingTopic> = TRENDING_TOPICS.with(|topics| topics.borrow().values().cloned().collect());
+    topics_vec.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.hashtag.cmp(&b.hashtag)));
+    topics_vec.into_iter().skip(offset as usize).take(limit as usize).collect()
+}
+
+#[query]
+fn get_related_hashtags(hashtag: String, limit: u64) -> Vec<String> {
+    let hashtag = match normalize_hashtag(&hashtag) {
+        Some(tag) => tag,
+        None => return Vec::new(),
+    };
+    HASHTAG_COOCCURRENCE.with(|cooccurrence| {
+        let mut related: Vec<(String, u64)> = cooccurrence.borrow().get(&hashtag)
+            .map(|related| related.iter().map(|(tag, count)| (tag.clone(), *count)).collect())
+            .unwrap_or_default();
+        related.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        related.truncate(limit as usize);
+        related.into_iter().map(|(tag, _)| tag).collect()
     })
 }
 
-// Search functions
+// Like get_trending_topics, but `count` and `distinct_authors` only cover uses within the last `window_secs`
 #[query]
-fn search_users(query: String) -> Vec<UserProfile> {
-    let query_lower = query.to_lowercase();
-    PROFILES.with(|profiles| {
-        profiles.borrow().values()
-            .filter(|profile| {
-                profile.username.to_lowercase().contains(&query_lower) ||
-                profile.bio.iter().any(|bio| bio.to_lowercase().contains(&query_lower))
+fn get_trending_topics_in_window(window_secs: u64, limit: u64) -> Vec<TrendingTopic> {
+    trending_topics_since(time().saturating_sub(window_secs.saturating_mul(1_000_000_000)), limit)
+}
+
+fn trending_topics_since(cutoff: u64, limit: u64) -> Vec<TrendingTopic> {
+    let mut topics_vec: Vec<TrendingTopic> = TOPIC_USAGE.with(|usage| {
+        usage.borrow().iter()
+            .filter_map(|(hashtag, uses)| {
+                // Timestamps are in insertion order, so the in-window uses are a suffix
+                let recent: Vec<Principal> = uses.iter().rev().take_while(|(used_at, _)| *used_at >= cutoff).map(|(_, author)| *author).collect();
+                let last_used = uses.back()?.0;
+                let distinct_authors = recent.iter().collect::<HashSet<_>>().len() as u64;
+                let count = recent.len() as u64;
+                (count > 0).then(|| TrendingTopic { hashtag: hashtag.clone(), count, distinct_authors, last_used })
             })
-            .cloned()
             .collect()
-    })
+    });
+    topics_vec.sort_by(|a, b| {
+        b.distinct_authors.cmp(&a.distinct_authors)
+            .then_with(|| b.last_used.cmp(&a.last_used))
+            .then_with(|| b.count.cmp(&a.count))
+    });
+    topics_vec.truncate(limit as usize);
+    topics_vec
 }
 
 #[query]
-fn search_posts_by_hashtag(hashtag: String) -> Vec<Post> {
-    POSTS.with(|posts| {
+fn get_hashtag_stats(hashtag: String) -> Result<HashtagStats, String> {
+    let hashtag = match normalize_hashtag(&hashtag) {
+        Some(tag) => tag,
+        None => return Result::Err("Invalid hashtag".to_string()),
+    };
+    let topic = match TRENDING_TOPICS.with(|topics| topics.borrow().get(&hashtag).cloned()) {
+        Some(topic) => topic,
+        None => return Result::Err("Hashtag has never been used".to_string()),
+    };
+
+    let (post_count, total_engagement, first_seen) = POSTS.with(|posts| {
         posts.borrow().values()
             .filter(|post| post.hashtags.contains(&hashtag))
-            .cloned()
-            .collect()
+            .fold((0u64, 0u64, topic.last_used), |(count, engagement, first_seen), post| {
+                let interactions = post.likes.len() as u64 + post.comments.len() as u64 + post.reshare_count;
+                (count + 1, engagement + interactions, first_seen.min(post.created_at))
+            })
+    });
+    let trending = trending_topics_since(time().saturating_sub(ACTIVE_TOPIC_WINDOW_NS), TRENDING_HASHTAG_RANK)
+        .iter()
+        .any(|trending| trending.hashtag == hashtag);
+
+    Result::Ok(HashtagStats {
+        hashtag,
+        post_count,
+        total_engagement,
+        first_seen,
+        last_used: topic.last_used,
+        trending,
     })
 }
 
-// Notification functions
+// Platform stats
 #[query]
-fn get_notifications() -> Vec<Notification> {
-    let caller = ic_cdk::caller();
-    NOTIFICATIONS.with(|notifications| {
-        notifications.borrow().values()
-            .filter(|notification| notification.recipient == caller)
-            .cloned()
-            .collect()
-    })
+fn get_platform_stats() -> PlatformStats {
+    let now = time();
+    PlatformStats {
+        total_users: PROFILES.with(|profiles| profiles.borrow().values().filter(|profile| profile.active).count() as u64),
+        total_posts: POSTS.with(|posts| posts.borrow().len() as u64),
+        total_comments: COMMENTS.with(|comments| comments.borrow().len() as u64),
+        total_messages: MESSAGES.with(|messages| messages.borrow().len() as u64),
+        active_trending_topics: TRENDING_TOPICS.with(|topics| {
+            topics.borrow().values()
+                .filter(|topic| now.saturating_sub(topic.last_used) < ACTIVE_TOPIC_WINDOW_NS)
+                .count() as u64
+        }),
+        total_tokens_in_circulation: WALLETS.with(|wallets| {
+            wallets.borrow().values().fold(0u64, |total, wallet| total.saturating_add(wallet.balance))
+        }),
+    }
 }
 
 #[update]
-fn mark_notification_as_read(notification_id: u64) -> Result<(), String> {
+fn mute_hashtag(hashtag: String) -> Result<(), String> {
     let caller = ic_cdk::caller();
-    NOTIFICATIONS.with(|notifications| {
-        let mut notifications = notifications.borrow_mut();
-        if let Some(notification) = notifications.get_mut(&notification_id) {
-            if notification.recipient == caller {
-                notification.read = true;
-                Result::Ok(())
-            } else {
-                Result::Err("Not authorized".to_string())
-            }
+    let hashtag = match normalize_hashtag(&hashtag) {
+        Some(tag) => tag,
+        None => return Result::Err("Invalid hashtag".to_string()),
+    };
+
+    MUTED_HASHTAGS.with(|muted| {
+        let mut muted = muted.borrow_mut();
+        let user_muted = muted.entry(caller).or_insert_with(Vec::new);
+        if user_muted.contains(&hashtag) {
+            Result::Err("Hashtag already muted".to_string())
         } else {
-            Result::Err("Notification not found".to_string())
+            user_muted.push(hashtag);
+            Result::Ok(())
         }
     })
 }
 
 #[update]
-fn mark_all_notifications_as_read() -> Result<(), String> {
+fn unmute_hashtag(hashtag: String) -> Result<(), String> {
     let caller = ic_cdk::caller();
-    NOTIFICATIONS.with(|notifications| {
-        let mut notifications = notifications.borrow_mut();
-        for notification in notifications.values_mut() {
-            if notification.recipient == caller {
-                notification.read = true;
+    let hashtag = normalize_hashtag(&hashtag).unwrap_or(hashtag);
+
+    MUTED_HASHTAGS.with(|muted| {
+        let mut muted = muted.borrow_mut();
+        if let Some(user_muted) = muted.get_mut(&caller) {
+            if let Some(pos) = user_muted.iter().position(|tag| *tag == hashtag) {
+                user_muted.remove(pos);
+                return Result::Ok(());
             }
         }
+        Result::Err("Hashtag not muted".to_string())
+    })
+}
+
+// The caller's own tags by affinity first, then trending tags they haven't used yet
+#[query]
+fn get_suggested_hashtags(limit: u64) -> Vec<String> {
+    let caller = ic_cdk::caller();
+    let muted = MUTED_HASHTAGS.with(|muted| muted.borrow().get(&caller).cloned().unwrap_or_default());
+
+    let mut own_tags: Vec<(String, u64)> = CONTENT_AFFINITY.with(|affinity| {
+        affinity.borrow()
+            .get(&caller)
+            .map(|user_affinity| user_affinity.iter().map(|(tag, weight)| (tag.clone(), *weight)).collect())
+            .unwrap_or_default()
+    });
+    own_tags.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+    let mut trending: Vec<TrendingTopic> = TRENDING_TOPICS.with(|topics| topics.borrow().values().cloned().collect());
+    sort_by_trending_score(&mut trending, time());
+
+    let mut suggestions: Vec<String> = own_tags.iter().map(|(tag, _)| tag.clone()).collect();
+    for topic in trending {
+        if !suggestions.contains(&topic.hashtag) {
+            suggestions.push(topic.hashtag);
+        }
+    }
+
+    suggestions.retain(|tag| !muted.contains(tag));
+    suggestions.truncate(limit as usize);
+    suggestions
+}
+
+// Lists are ordered by id; group messages cover every group the caller still belongs to
+#[query]
+fn export_my_data() -> UserDataExport {
+    let caller = ic_cdk::caller();
+
+    let mut posts: Vec<Post> = POSTS.with(|posts| {
+        posts.borrow().values().filter(|post| post.author == caller).cloned().collect()
+    });
+    posts.sort_by_key(|post| post.id);
+
+    let mut comments: Vec<Comment> = COMMENTS.with(|comments| {
+        comments.borrow().values().filter(|comment| comment.author == caller).cloned().collect()
+    });
+    comments.sort_by_key(|comment| comment.id);
+
+    let group_threads: HashSet<String> = CHAT_THREADS.with(|threads| {
+        threads.borrow().values()
+            .filter(|thread| thread.is_group && thread.participants.contains(&caller))
+            .map(|thread| thread.id.clone())
+            .collect()
+    });
+    let mut messages: Vec<Message> = MESSAGES.with(|messages| {
+        messages.borrow().values()
+            .filter(|message| message.from == caller || message.to == caller || group_threads.contains(&message.thread_id))
+            .map(|message| message_as_seen_by(caller, message.clone()))
+            .collect()
+    });
+    messages.sort_by_key(|message| message.id);
+
+    let following = FOLLOWS.with(|follows| follows.borrow().get(&caller).cloned().unwrap_or_default());
+    let followers: Vec<Principal> = FOLLOWS.with(|follows| {
+        follows.borrow().iter()
+            .filter(|(_, following)| following.contains(&caller))
+            .map(|(follower, _)| *follower)
+            .collect()
+    });
+
+    let mut transactions: Vec<Transaction> = TRANSACTIONS.with(|transactions| {
+        transactions.borrow().values().filter(|tx| tx.from == caller || tx.to == caller).cloned().collect()
+    });
+    transactions.sort_by_key(|tx| tx.id);
+
+    UserDataExport {
+        user_id: caller,
+        exported_at: time(),
+        profile: PROFILES.with(|profiles| profiles.borrow().get(&caller).cloned()),
+        posts,
+        comments,
+        messages,
+        following,
+        followers,
+        transactions,
+    }
+}
+
+// Identity function
+#[query]
+fn whoami() -> Principal {
+    ic_cdk::caller()
+}
+
+// Everything the app shell needs on startup in one round trip
+#[query]
+fn get_session() -> SessionInfo {
+    let caller = ic_cdk::caller();
+    let profile = PROFILES.with(|profiles| profiles.borrow().get(&caller).cloned());
+    let unread_notifications = NOTIFICATIONS.with(|notifications| {
+        notifications.borrow().values()
+            .filter(|notification| notification.recipient == caller && !notification.read)
+            .count() as u64
+    });
+    let unread_messages = unread_counts_by_thread(caller).values().sum();
+
+    SessionInfo {
+        principal: caller,
+        needs_profile: profile.is_none(),
+        profile,
+        unread_notifications,
+        unread_messages,
+    }
+}
+
+// Poll functions
+fn poll_results(poll: &Poll, now: u64) -> PollResults {
+    let vote_counts: Vec<u64> = poll.votes.iter().map(|voters| voters.len() as u64).collect();
+    PollResults {
+        poll_id: poll.id,
+        post_id: poll.post_id,
+        question: poll.question.clone(),
+        options: poll.options.clone(),
+        total_votes: vote_counts.iter().sum(),
+        vote_counts,
+        expires_at: poll.expires_at,
+        closed: now >= poll.expires_at,
+    }
+}
+
+#[update]
+fn create_poll(question: String, options: Vec<String>, duration_secs: u64) -> Result<PollResults, String> {
+    let author = ic_cdk::caller();
+
+    if question.trim().is_empty() {
+        return Result::Err("Poll question cannot be empty".to_string());
+    }
+    if options.len() < MIN_POLL_OPTIONS || options.len() > MAX_POLL_OPTIONS {
+        return Result::Err(format!("A poll needs between {} and {} options", MIN_POLL_OPTIONS, MAX_POLL_OPTIONS));
+    }
+    if options.iter().any(|option| option.trim().is_empty()) {
+        return Result::Err("Poll options cannot be empty".to_string());
+    }
+    if duration_secs == 0 {
+        return Result::Err("Poll duration must be greater than 0".to_string());
+    }
+
+    let poll_id = get_next_id(&POLL_COUNTER);
+    let post = match create_post_internal(author, question.clone(), PostType::Poll { poll_id }, Vec::new(), Visibility::Public, None) {
+        Result::Ok(post) => post,
+        Result::Err(e) => return Result::Err(e),
+    };
+
+    let now = time();
+    let poll = Poll {
+        id: poll_id,
+        post_id: post.id,
+        question,
+        votes: vec![Vec::new(); options.len()],
+        options,
+        created_at: now,
+        expires_at: now.saturating_add(duration_secs.saturating_mul(1_000_000_000)),
+    };
+
+    POLLS.with(|polls| {
+        polls.borrow_mut().insert(poll_id, poll.clone());
+    });
+
+    Result::Ok(poll_results(&poll, now))
+}
+
+#[update]
+fn vote_poll(poll_id: u64, option_index: u64) -> Result<PollResults, String> {
+    let voter = ic_cdk::caller();
+    let now = time();
+
+    POLLS.with(|polls| {
+        let mut polls = polls.borrow_mut();
+        let poll = match polls.get_mut(&poll_id) {
+            Some(poll) => poll,
+            None => return Result::Err("Poll not found".to_string()),
+        };
+
+        if now >= poll.expires_at {
+            return Result::Err("Poll has ended".to_string());
+        }
+        if poll.votes.iter().any(|voters| voters.contains(&voter)) {
+            return Result::Err("Already voted".to_string());
+        }
+
+        match poll.votes.get_mut(option_index as usize) {
+            Some(voters) => voters.push(voter),
+            None => return Result::Err("Invalid option".to_string()),
+        }
+
+        Result::Ok(poll_results(poll, now))
+    })
+}
+
+#[query]
+fn get_poll_results(poll_id: u64) -> Result<PollResults, String> {
+    let now = time();
+    POLLS.with(|polls| {
+        polls.borrow().get(&poll_id)
+            .map(|poll| Result::Ok(poll_results(poll, now)))
+            .unwrap_or(Result::Err("Poll not found".to_string()))
+    })
+}
+
+// Admin functions
+#[update]
+fn add_admin(user_id: Principal) -> Result<(), String> {
+    if !is_admin(ic_cdk::caller()) {
+        return Result::Err("Not authorized".to_string());
+    }
+    ADMINS.with(|admins| {
+        let mut admins = admins.borrow_mut();
+        if admins.contains(&user_id) {
+            return Result::Err("User is already an admin".to_string());
+        }
+        admins.push(user_id);
+        Result::Ok(())
+    })
+}
+
+// An admin may remove themselves, but never the last admin
+#[update]
+fn remove_admin(user_id: Principal) -> Result<(), String> {
+    if !is_admin(ic_cdk::caller()) {
+        return Result::Err("Not authorized".to_string());
+    }
+    ADMINS.with(|admins| {
+        let mut admins = admins.borrow_mut();
+        let pos = match admins.iter().position(|&admin| admin == user_id) {
+            Some(pos) => pos,
+            None => return Result::Err("User is not an admin".to_string()),
+        };
+        if admins.len() == 1 {
+            return Result::Err("Cannot remove the last admin".to_string());
+        }
+        admins.remove(pos);
         Result::Ok(())
     })
 }
 
-// Message functions
+#[query]
+fn get_admins() -> Vec<Principal> {
+    ADMINS.with(|admins| admins.borrow().clone())
+}
+
+// Moderation functions
 #[update]
-fn send_message(to_user_id: Principal, content: String) -> Result<Message, String> {
-    let from_user = ic_cdk::caller();
-    
-    if from_user == to_user_id {
-        return Result::Err("Cannot send message to yourself".to_string());
+fn report_post(post_id: u64, reason: String) -> Result<Report, String> {
+    let reporter = ic_cdk::caller();
+
+    if !POSTS.with(|posts| posts.borrow().contains_key(&post_id)) {
+        return Result::Err("Post not found".to_string());
     }
 
-    let message_id = get_next_id(&MESSAGE_COUNTER);
-    let message = Message {
-        id: message_id,
-        from: from_user,
-        to: to_user_id,
-        content,
+    let already_reported = REPORTS.with(|reports| {
+        reports.borrow().values().any(|report| report.post_id == post_id && report.reporter == reporter)
+    });
+    if already_reported {
+        return Result::Err("Post already reported".to_string());
+    }
+
+    let report_id = get_next_id(&REPORT_COUNTER);
+    let report = Report {
+        id: report_id,
+        reporter,
+        post_id,
+        reason,
         created_at: time(),
-        read: false,
+        resolved: false,
     };
 
-    MESSAGES.with(|messages| {
-        messages.borrow_mut().insert(message_id, message.clone());
+    // Each reporter can only report a post once, so this counts distinct users
+    let open_reports = REPORTS.with(|reports| {
+        let mut reports = reports.borrow_mut();
+        reports.insert(report_id, report.clone());
+        reports.values().filter(|report| report.post_id == post_id && !report.resolved).count() as u64
     });
+    if open_reports >= REPORT_HIDE_THRESHOLD.with(|threshold| *threshold.borrow()) {
+        POSTS.with(|posts| {
+            if let Some(post) = posts.borrow_mut().get_mut(&post_id) {
+                post.hidden_by_reports = true;
+            }
+        });
+    }
 
-    // Create or update chat thread
-    let thread_id = if from_user < to_user_id {
-        format!("{}_{}", from_user, to_user_id)
-    } else {
-        format!("{}_{}", to_user_id, from_user)
-    };
+    Result::Ok(report)
+}
 
-    CHAT_THREADS.with(|threads| {
-        let mut threads = threads.borrow_mut();
-        let thread = threads.entry(thread_id.clone()).or_insert_with(|| ChatThread {
-            id: thread_id,
-            participants: vec![from_user, to_user_id],
-            last_message: None,
-            updated_at: time(),
-        });
-        thread.last_message = Some(message.clone());
-        thread.updated_at = time();
-    });
+// Admin override for a post hidden by reports; its open reports are resolved so they don't hide it again
+#[update]
+fn restore_post(post_id: u64) -> Result<Post, String> {
+    if !is_admin(ic_cdk::caller()) {
+        return Result::Err("Not authorized".to_string());
+    }
 
-    // Create notification
-    let notification_id = get_next_id(&NOTIFICATION_COUNTER);
-    let notification = Notification {
-        id: notification_id,
-        recipient: to_user_id,
-        notification_type: NotificationType::Message { user_id: from_user, message_id },
-        created_at: time(),
-        read: false,
-    };
-    NOTIFICATIONS.with(|notifications| {
-        notifications.borrow_mut().insert(notification_id, notification);
+    let restored = POSTS.with(|posts| {
+        match posts.borrow_mut().get_mut(&post_id) {
+            Some(post) => {
+                post.hidden_by_reports = false;
+                Result::Ok(post.clone())
+            }
+            None => Result::Err("Post not found".to_string()),
+        }
     });
+    if let Result::Ok(_) = &restored {
+        REPORTS.with(|reports| {
+            for report in reports.borrow_mut().values_mut().filter(|report| report.post_id == post_id) {
+                report.resolved = true;
+            }
+        });
+    }
+    restored
+}
 
-    Result::Ok(message)
+#[update]
+fn set_feed_author_cap(cap: u64) -> Result<(), String> {
+    if !is_admin(ic_cdk::caller()) {
+        return Result::Err("Not authorized".to_string());
+    }
+    if cap == 0 {
+        return Result::Err("Cap must be at least 1".to_string());
+    }
+    FEED_AUTHOR_CAP.with(|current| *current.borrow_mut() = cap as usize);
+    Result::Ok(())
 }
 
 #[query]
-fn get_messages(with_user_id: Principal) -> Vec<Message> {
-    let caller = ic_cdk::caller();
-    MESSAGES.with(|messages| {
-        messages.borrow().values()
-            .filter(|message| {
-                (message.from == caller && message.to == with_user_id) ||
-                (message.from == with_user_id && message.to == caller)
-            })
-            .cloned()
-            .collect()
-    })
+fn get_max_post_length() -> u64 {
+    max_post_length() as u64
 }
 
-#[query]
-fn get_chat_threads() -> Vec<ChatThread> {
-    let caller = ic_cdk::caller();
-    CHAT_THREADS.with(|threads| {
-        threads.borrow().values()
-            .filter(|thread| thread.participants.contains(&caller))
-            .cloned()
-            .collect()
-    })
+// Existing posts over a lowered limit are kept; the new limit applies to new posts and edits
+#[update]
+fn set_max_post_length(max_chars: u64) -> Result<(), String> {
+    if !is_admin(ic_cdk::caller()) {
+        return Result::Err("Not authorized".to_string());
+    }
+    if max_chars == 0 || max_chars > POST_LENGTH_CEILING as u64 {
+        return Result::Err(format!("Post length limit must be between 1 and {}", POST_LENGTH_CEILING));
+    }
+    MAX_POST_LENGTH.with(|max| *max.borrow_mut() = max_chars as usize);
+    Result::Ok(())
 }
 
+// Points `alias` at `canonical` and folds existing uses of the alias into the canonical tag
 #[update]
-fn mark_messages_as_read(from_user_id: Principal) -> u64 {
-    let caller = ic_cdk::caller();
-    let mut count = 0u64;
-    
-    MESSAGES.with(|messages| {
-        let mut messages = messages.borrow_mut();
-        for message in messages.values_mut() {
-            if message.from == from_user_id && message.to == caller && !message.read {
-                message.read = true;
-                count += 1;
-            }
+fn set_hashtag_alias(alias: String, canonical: String) -> Result<u64, String> {
+    if !is_admin(ic_cdk::caller()) {
+        return Result::Err("Not authorized".to_string());
+    }
+    let (alias, canonical) = match (lowercase_hashtag(&alias), normalize_hashtag(&canonical)) {
+        (Some(alias), Some(canonical)) => (alias, canonical),
+        _ => return Result::Err("Invalid hashtag".to_string()),
+    };
+    if alias == canonical {
+        return Result::Err("A hashtag cannot be an alias of itself".to_string());
+    }
+
+    HASHTAG_ALIASES.with(|aliases| {
+        let mut aliases = aliases.borrow_mut();
+        // Keep the map one level deep: anything that pointed at `alias` now points at `canonical`
+        for target in aliases.values_mut().filter(|target| **target == alias) {
+            *target = canonical.clone();
         }
+        aliases.insert(alias, canonical);
     });
-    
-    count
+    Result::Ok(migrate_stored_hashtags())
 }
 
-// Social graph functions
-#[query]
-fn get_mutual_connections(user_id: Principal) -> Vec<Principal> {
-    let caller = ic_cdk::caller();
-    let caller_following = FOLLOWS.with(|follows| {
-        follows.borrow().get(&caller).cloned().unwrap_or_default()
-    });
-    let user_following = FOLLOWS.with(|follows| {
-        follows.borrow().get(&user_id).cloned().unwrap_or_default()
-    });
-    
-    caller_following.into_iter()
-        .filter(|user| user_following.contains(user))
-        .collect()
+#[update]
+fn remove_hashtag_alias(alias: String) -> Result<(), String> {
+    if !is_admin(ic_cdk::caller()) {
+        return Result::Err("Not authorized".to_string());
+    }
+    let alias = match lowercase_hashtag(&alias) {
+        Some(alias) => alias,
+        None => return Result::Err("Invalid hashtag".to_string()),
+    };
+    match HASHTAG_ALIASES.with(|aliases| aliases.borrow_mut().remove(&alias)) {
+        Some(_) => Result::Ok(()),
+        None => Result::Err("Alias not found".to_string()),
+    }
 }
 
 #[query]
-fn suggest_connections(limit: u64) -> Vec<UserProfile> {
-    let caller = ic_cdk::caller();
-    let caller_following = FOLLOWS.with(|follows| {
-        follows.borrow().get(&caller).cloned().unwrap_or_default()
+fn get_hashtag_aliases() -> Vec<(String, String)> {
+    let mut aliases: Vec<(String, String)> = HASHTAG_ALIASES.with(|aliases| {
+        aliases.borrow().iter().map(|(alias, canonical)| (alias.clone(), canonical.clone())).collect()
     });
-    
-    let mut suggestions: Vec<(UserProfile, u64)> = Vec::new();
-    
-    PROFILES.with(|profiles| {
-        for profile in profiles.borrow().values() {
-            if profile.id != caller && !caller_following.contains(&profile.id) {
-                let mut score = 0u64;
-                
-                // Score based on mutual connections
-                let mutual_count = get_mutual_connections(profile.id).len() as u64;
-                score += mutual_count * 10;
-                
-                // Score based on content affinity
-                CONTENT_AFFINITY.with(|affinity| {
-                    if let Some(caller_affinity) = affinity.borrow().get(&caller) {
-                        if let Some(profile_affinity) = affinity.borrow().get(&profile.id) {
-                            for (hashtag, caller_score) in caller_affinity {
-                                if let Some(profile_score) = profile_affinity.get(hashtag) {
-                                    score += caller_score.min(profile_score);
-                                }
-                            }
-                        }
-                    }
-                });
-                
-                suggestions.push((profile.clone(), score));
-            }
-        }
-    });
-    
-    suggestions.sort_by(|a, b| b.1.cmp(&a.1));
-    suggestions.truncate(limit as usize);
-    suggestions.into_iter().map(|(profile, _)| profile).collect()
+    aliases.sort();
+    aliases
+}
+
+// One-off cleanup for tags stored before normalization; returns how many posts changed
+#[update]
+fn migrate_hashtags() -> Result<u64, String> {
+    if !is_admin(ic_cdk::caller()) {
+        return Result::Err("Not authorized".to_string());
+    }
+    Result::Ok(migrate_stored_hashtags())
+}
+
+// Replaces the whole word list; pass ContentFilterMode::Off to disable filtering
+#[update]
+fn set_content_filter(words: Vec<String>, mode: ContentFilterMode) -> Result<(), String> {
+    if !is_admin(ic_cdk::caller()) {
+        return Result::Err("Not authorized".to_string());
+    }
+    let words: HashSet<String> = words.iter()
+        .map(|word| word.trim().to_lowercase())
+        .filter(|word| !word.is_empty())
+        .collect();
+    CONTENT_FILTER_WORDS.with(|current| *current.borrow_mut() = words);
+    CONTENT_FILTER_MODE.with(|current| *current.borrow_mut() = mode);
+    Result::Ok(())
+}
+
+#[update]
+fn set_report_hide_threshold(threshold: u64) -> Result<(), String> {
+    if !is_admin(ic_cdk::caller()) {
+        return Result::Err("Not authorized".to_string());
+    }
+    if threshold == 0 {
+        return Result::Err("Threshold must be at least 1".to_string());
+    }
+    REPORT_HIDE_THRESHOLD.with(|current| *current.borrow_mut() = threshold);
+    Result::Ok(())
 }
 
 #[query]
-fn get_connection_strength(user_id: Principal) -> u64 {
-    let caller = ic_cdk::caller();
-    INTERACTION_GRAPH.with(|graph| {
-        graph.borrow()
-            .get(&caller)
-            .and_then(|interactions| interactions.get(&user_id))
-            .copied()
-            .unwrap_or(0)
+fn get_reports() -> Result<Vec<Report>, String> {
+    if !is_admin(ic_cdk::caller()) {
+        return Result::Err("Not authorized".to_string());
+    }
+
+    REPORTS.with(|reports| {
+        let mut reports_vec: Vec<Report> = reports.borrow().values().cloned().collect();
+        reports_vec.sort_by_key(|r| std::cmp::Reverse(r.created_at));
+        Result::Ok(reports_vec)
     })
 }
 
-// Trending topics
 #[query]
-fn get_trending_topics(limit: u64) -> Vec<TrendingTopic> {
-    TRENDING_TOPICS.with(|topics| {
-        let mut topics_vec: Vec<TrendingTopic> = topics.borrow().values().cloned().collect();
-        topics_vec.sort_by(|a, b| b.count.cmp(&a.count));
-        topics_vec.truncate(limit as usize);
-        topics_vec
+fn get_flagged_posts() -> Result<Vec<Post>, String> {
+    if !is_admin(ic_cdk::caller()) {
+        return Result::Err("Not authorized".to_string());
+    }
+    POSTS.with(|posts| {
+        let mut flagged: Vec<Post> = posts.borrow().values().filter(|post| post.flagged).cloned().collect();
+        flagged.sort_by_key(|post| std::cmp::Reverse(post.created_at));
+        Result::Ok(flagged)
     })
 }
 
-// Identity function
-#[query]
-fn whoami() -> Principal {
-    ic_cdk::caller()
+#[update]
+fn resolve_report(report_id: u64) -> Result<Report, String> {
+    if !is_admin(ic_cdk::caller()) {
+        return Result::Err("Not authorized".to_string());
+    }
+
+    REPORTS.with(|reports| {
+        let mut reports = reports.borrow_mut();
+        if let Some(report) = reports.get_mut(&report_id) {
+            report.resolved = true;
+            Result::Ok(report.clone())
+        } else {
+            Result::Err("Report not found".to_string())
+        }
+    })
 }
 
 // Wallet helper functions
 fn create_account_identifier(user_id: Principal) -> String {
-    format!("account-{}", user_id.to_string())
+    format!("account-{}", user_id)
 }
 
 fn get_or_create_wallet(user_id: Principal) -> Wallet {
@@ -985,22 +5636,120 @@ fn get_or_create_wallet(user_id: Principal) -> Wallet {
         if let Some(wallet) = wallets.get(&user_id) {
             wallet.clone()
         } else {
-            // Create new wallet with initial test ICP
+            // Create new wallet with the signup grant of 1000 tokens
             let account_id = create_account_identifier(user_id);
-            // Give 1000 ICP (in e8s = 1000 * 100_000_000 = 100_000_000_000)
-            let initial_balance = 100_000_000_000u64; // 1000 ICP
             let wallet = Wallet {
                 user_id,
                 account_id,
-                balance: initial_balance,
+                balance: INITIAL_WALLET_BALANCE,
                 created_at: time(),
             };
             wallets.insert(user_id, wallet.clone());
+            TOTAL_SUPPLY.with(|supply| {
+                let mut supply = supply.borrow_mut();
+                *supply = supply.saturating_add(INITIAL_WALLET_BALANCE);
+            });
             wallet
         }
     })
 }
 
+// Credits newly created tokens to an existing wallet; fails instead of overflowing the supply
+fn credit_new_tokens(user_id: Principal, amount: u64) -> Result<u64, String> {
+    let new_supply = match TOTAL_SUPPLY.with(|supply| supply.borrow().checked_add(amount)) {
+        Some(supply) => supply,
+        None => return Result::Err("Amount exceeds the maximum token supply".to_string()),
+    };
+
+    let balance = WALLETS.with(|wallets| {
+        let mut wallets = wallets.borrow_mut();
+        match wallets.get_mut(&user_id) {
+            Some(wallet) => {
+                wallet.balance += amount;
+                Result::Ok(wallet.balance)
+            }
+            None => Result::Err("Wallet not found".to_string()),
+        }
+    });
+
+    if let Result::Ok(_) = balance {
+        TOTAL_SUPPLY.with(|supply| *supply.borrow_mut() = new_supply);
+    }
+    balance
+}
+
+// Transactions are stored as Pending before any balance moves, then settled to Completed or Failed
+fn record_pending_transaction(from: Principal, to: Principal, amount: u64, transaction_type: TransactionType) -> Transaction {
+    let transaction_id = get_next_id(&TRANSACTION_COUNTER);
+    let transaction = Transaction {
+        id: transaction_id,
+        from,
+        to,
+        amount,
+        timestamp: time(),
+        transaction_type,
+        status: TransactionStatus::Pending,
+        memo: None,
+    };
+    TRANSACTIONS.with(|transactions| {
+        transactions.borrow_mut().insert(transaction_id, transaction.clone());
+    });
+    transaction
+}
+
+fn settle_transaction(transaction_id: u64) -> Result<Transaction, String> {
+    let transaction = match TRANSACTIONS.with(|transactions| transactions.borrow().get(&transaction_id).cloned()) {
+        Some(transaction) => transaction,
+        None => return Result::Err("Transaction not found".to_string()),
+    };
+
+    // Check balance and move funds in one step
+    let moved = WALLETS.with(|wallets| {
+        let mut wallets = wallets.borrow_mut();
+        let sufficient = wallets.get(&transaction.from).is_some_and(|wallet| wallet.balance >= transaction.amount);
+        if !sufficient || !wallets.contains_key(&transaction.to) {
+            return false;
+        }
+        if let Some(from_wallet) = wallets.get_mut(&transaction.from) {
+            from_wallet.balance -= transaction.amount;
+        }
+        if let Some(to_wallet) = wallets.get_mut(&transaction.to) {
+            to_wallet.balance += transaction.amount;
+        }
+        true
+    });
+
+    let status = if moved { TransactionStatus::Completed } else { TransactionStatus::Failed };
+    let transaction = TRANSACTIONS.with(|transactions| {
+        let mut transactions = transactions.borrow_mut();
+        let stored = transactions.get_mut(&transaction_id).expect("transaction exists");
+        stored.status = status;
+        stored.clone()
+    });
+
+    if moved {
+        Result::Ok(transaction)
+    } else {
+        Result::Err(format!("Insufficient balance (transaction {} marked as failed)", transaction_id))
+    }
+}
+
+// Mints `amount` into the user's wallet (creating it if needed) and records a completed Reward
+fn issue_reward(to: Principal, amount: u64) -> Result<Transaction, String> {
+    get_or_create_wallet(to);
+    if let Result::Err(e) = credit_new_tokens(to, amount) {
+        return Result::Err(e);
+    }
+
+    let transaction = record_pending_transaction(ic_cdk::id(), to, amount, TransactionType::Reward);
+    TRANSACTIONS.with(|transactions| {
+        let mut transactions = transactions.borrow_mut();
+        let stored = transactions.get_mut(&transaction.id).expect("transaction exists");
+        stored.status = TransactionStatus::Completed;
+        Result::Ok(stored.clone())
+    })
+}
+
 // Wallet functions
 #[update]
 fn create_wallet() -> Result<Wallet, String> {
@@ -1026,15 +5775,56 @@ fn get_balance() -> Result<u64, String> {
 #[update]
 fn add_test_icp(amount: u64) -> Result<u64, String> {
     let user_id = ic_cdk::caller();
-    WALLETS.with(|wallets| {
-        let mut wallets = wallets.borrow_mut();
-        if let Some(wallet) = wallets.get_mut(&user_id) {
-            wallet.balance += amount;
-            Result::Ok(wallet.balance)
-        } else {
-            Result::Err("Wallet not found".to_string())
+    credit_new_tokens(user_id, amount)
+}
+
+#[query]
+fn token_metadata() -> TokenMetadata {
+    TokenMetadata {
+        name: TOKEN_NAME.to_string(),
+        symbol: TOKEN_SYMBOL.to_string(),
+        decimals: TOKEN_DECIMALS,
+        total_supply: TOTAL_SUPPLY.with(|supply| *supply.borrow()),
+    }
+}
+
+// Admin-only reward issuance; minted transfers come from the canister's own principal
+#[update]
+fn mint(to: Principal, amount: u64) -> Result<Transaction, String> {
+    if !is_admin(ic_cdk::caller()) {
+        return Result::Err("Not authorized".to_string());
+    }
+    if amount == 0 {
+        return Result::Err("Amount must be greater than 0".to_string());
+    }
+
+    issue_reward(to, amount)
+}
+
+#[update]
+fn claim_daily_reward() -> Result<Transaction, String> {
+    let user_id = ic_cdk::caller();
+    let now = time();
+
+    if let Some(last_claim) = LAST_REWARD_CLAIM.with(|claims| claims.borrow().get(&user_id).copied()) {
+        let elapsed = now.saturating_sub(last_claim);
+        if elapsed < DAILY_REWARD_INTERVAL_NS {
+            let remaining_secs = (DAILY_REWARD_INTERVAL_NS - elapsed) / 1_000_000_000;
+            return Result::Err(format!(
+                "Daily reward already claimed; try again in {}h {}m",
+                remaining_secs / 3600,
+                (remaining_secs % 3600) / 60
+            ));
         }
-    })
+    }
+
+    let transaction = issue_reward(user_id, DAILY_REWARD_AMOUNT);
+    if let Result::Ok(_) = &transaction {
+        LAST_REWARD_CLAIM.with(|claims| {
+            claims.borrow_mut().insert(user_id, now);
+        });
+    }
+    transaction
 }
 
 #[update]
@@ -1050,44 +5840,46 @@ fn transfer_tokens(to_user_id: Principal, amount: u64) -> Result<Transaction, St
     }
     
     // Get or create wallets
-    let from_wallet = get_or_create_wallet(from_user_id);
-    let _to_wallet = get_or_create_wallet(to_user_id);
-    
-    // Check balance
-    if from_wallet.balance < amount {
-        return Result::Err("Insufficient balance".to_string());
-    }
-    
-    // Create transaction
-    let transaction_id = get_next_id(&TRANSACTION_COUNTER);
-    let transaction = Transaction {
-        id: transaction_id,
-        from: from_user_id,
-        to: to_user_id,
-        amount,
-        timestamp: time(),
-        transaction_type: TransactionType::Transfer,
-        status: TransactionStatus::Completed,
-        memo: None,
-    };
-    
-    // Update balances
-    WALLETS.with(|wallets| {
-        let mut wallets = wallets.borrow_mut();
-        if let Some(from_wallet) = wallets.get_mut(&from_user_id) {
-            from_wallet.balance -= amount;
-        }
-        if let Some(to_wallet) = wallets.get_mut(&to_user_id) {
-            to_wallet.balance += amount;
-        }
-    });
-    
-    // Store transaction
+    get_or_create_wallet(from_user_id);
+    get_or_create_wallet(to_user_id);
+
+    let transaction = record_pending_transaction(from_user_id, to_user_id, amount, TransactionType::Transfer);
+    settle_transaction(transaction.id)
+}
+
+#[query]
+fn get_transaction(transaction_id: u64) -> Result<Transaction, String> {
+    let caller = ic_cdk::caller();
     TRANSACTIONS.with(|transactions| {
-        transactions.borrow_mut().insert(transaction_id, transaction.clone());
-    });
-    
-    Result::Ok(transaction)
+        match transactions.borrow().get(&transaction_id) {
+            Some(tx) if tx.from == caller || tx.to == caller => Result::Ok(tx.clone()),
+            Some(_) => Result::Err("Not authorized".to_string()),
+            None => Result::Err("Transaction not found".to_string()),
+        }
+    })
+}
+
+// Re-attempts a failed transaction; only the sender can retry
+#[update]
+fn retry_transaction(transaction_id: u64) -> Result<Transaction, String> {
+    let caller = ic_cdk::caller();
+    let transaction = match TRANSACTIONS.with(|transactions| transactions.borrow().get(&transaction_id).cloned()) {
+        Some(transaction) => transaction,
+        None => return Result::Err("Transaction not found".to_string()),
+    };
+
+    if transaction.from != caller {
+        return Result::Err("Not authorized".to_string());
+    }
+    if !matches!(transaction.status, TransactionStatus::Failed) {
+        return Result::Err("Only failed transactions can be retried".to_string());
+    }
+    // A Failed escrow purchase was refunded on purpose, so settling it here would undo the refund
+    if ESCROWS.with(|escrows| escrows.borrow().values().any(|escrow| escrow.transaction_id == transaction_id)) {
+        return Result::Err("Refunded escrow purchases cannot be retried".to_string());
+    }
+
+    settle_transaction(transaction_id)
 }
 
 #[query]
@@ -1098,7 +5890,22 @@ fn get_transaction_history(limit: u64) -> Vec<Transaction> {
             .filter(|tx| tx.from == user_id || tx.to == user_id)
             .cloned()
             .collect();
-        user_transactions.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+        user_transactions.sort_by_key(|b| std::cmp::Reverse(b.timestamp));
+        user_transactions.truncate(limit as usize);
+        user_transactions
+    })
+}
+
+#[query]
+fn get_transactions(filter: Option<TransactionType>, limit: u64) -> Vec<Transaction> {
+    let user_id = ic_cdk::caller();
+    TRANSACTIONS.with(|transactions| {
+        let mut user_transactions: Vec<Transaction> = transactions.borrow().values()
+            .filter(|tx| tx.from == user_id || tx.to == user_id)
+            .filter(|tx| filter.as_ref().is_none_or(|kind| &tx.transaction_type == kind))
+            .cloned()
+            .collect();
+        user_transactions.sort_by_key(|tx| std::cmp::Reverse(tx.timestamp));
         user_transactions.truncate(limit as usize);
         user_transactions
     })
@@ -1117,42 +5924,664 @@ fn tip_user(user_id: Principal, amount: u64) -> Result<Transaction, String> {
     }
     
     // Get or create wallets
-    let from_wallet = get_or_create_wallet(from_user_id);
-    let _to_wallet = get_or_create_wallet(user_id);
-    
-    // Check balance
-    if from_wallet.balance < amount {
+    get_or_create_wallet(from_user_id);
+    get_or_create_wallet(user_id);
+
+    let transaction = record_pending_transaction(from_user_id, user_id, amount, TransactionType::Tip);
+    settle_transaction(transaction.id)
+}
+
+// Leaderboards count completed transactions only, largest total first with ties broken by principal
+fn transaction_leaderboard(kinds: &[TransactionType], by_sender: bool, limit: u64) -> Vec<(Principal, u64)> {
+    let mut totals: HashMap<Principal, u64> = HashMap::new();
+    TRANSACTIONS.with(|transactions| {
+        for tx in transactions.borrow().values() {
+            if kinds.contains(&tx.transaction_type) && matches!(tx.status, TransactionStatus::Completed) {
+                let user = if by_sender { tx.from } else { tx.to };
+                let total = totals.entry(user).or_insert(0);
+                *total = total.saturating_add(tx.amount);
+            }
+        }
+    });
+    let mut ranked: Vec<(Principal, u64)> = totals.into_iter().filter(|(user, _)| !is_deactivated(*user)).collect();
+    ranked.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    ranked.truncate(limit as usize);
+    ranked
+}
+
+#[query]
+fn get_top_tippers(limit: u64) -> Vec<(Principal, u64)> {
+    transaction_leaderboard(&[TransactionType::Tip], true, limit)
+}
+
+// Earnings are tips received plus rewards
+#[query]
+fn get_top_earners(limit: u64) -> Vec<(Principal, u64)> {
+    transaction_leaderboard(&[TransactionType::Tip, TransactionType::Reward], false, limit)
+}
+
+// Escrow functions
+#[update]
+fn create_escrow(to: Principal, amount: u64) -> Result<u64, String> {
+    let buyer = ic_cdk::caller();
+    if buyer == to {
+        return Result::Err("Cannot buy from yourself".to_string());
+    }
+    if amount == 0 {
+        return Result::Err("Amount must be greater than 0".to_string());
+    }
+
+    get_or_create_wallet(buyer);
+    get_or_create_wallet(to);
+
+    let locked = WALLETS.with(|wallets| {
+        match wallets.borrow_mut().get_mut(&buyer) {
+            Some(wallet) if wallet.balance >= amount => {
+                wallet.balance -= amount;
+                true
+            }
+            _ => false,
+        }
+    });
+    if !locked {
         return Result::Err("Insufficient balance".to_string());
     }
-    
-    // Create transaction
-    let transaction_id = get_next_id(&TRANSACTION_COUNTER);
-    let transaction = Transaction {
-        id: transaction_id,
-        from: from_user_id,
-        to: user_id,
+
+    let escrow_id = get_next_id(&ESCROW_COUNTER);
+    let transaction = record_pending_transaction(buyer, to, amount, TransactionType::Purchase);
+    TRANSACTIONS.with(|transactions| {
+        if let Some(stored) = transactions.borrow_mut().get_mut(&transaction.id) {
+            stored.memo = Some(format!("Escrow {}", escrow_id));
+        }
+    });
+    let escrow = Escrow {
+        id: escrow_id,
+        buyer,
+        seller: to,
         amount,
-        timestamp: time(),
-        transaction_type: TransactionType::Tip,
-        status: TransactionStatus::Completed,
-        memo: None,
+        status: EscrowStatus::Held,
+        created_at: time(),
+        resolved_at: None,
+        transaction_id: transaction.id,
     };
-    
-    // Update balances
-    WALLETS.with(|wallets| {
-        let mut wallets = wallets.borrow_mut();
-        if let Some(from_wallet) = wallets.get_mut(&from_user_id) {
-            from_wallet.balance -= amount;
-        }
-        if let Some(to_wallet) = wallets.get_mut(&user_id) {
-            to_wallet.balance += amount;
+    ESCROWS.with(|escrows| {
+        escrows.borrow_mut().insert(escrow_id, escrow);
+    });
+    Result::Ok(escrow_id)
+}
+
+// Moves a held escrow out of Held once `allowed` approves the caller; funds go to `recipient(escrow)`
+fn resolve_escrow(escrow_id: u64, status: EscrowStatus, allowed: impl Fn(&Escrow) -> bool, recipient: impl Fn(&Escrow) -> Principal) -> Result<Escrow, String> {
+    let resolved = ESCROWS.with(|escrows| {
+        let mut escrows = escrows.borrow_mut();
+        match escrows.get_mut(&escrow_id) {
+            None => Result::Err("Escrow not found".to_string()),
+            Some(escrow) if !allowed(escrow) => Result::Err("Not authorized".to_string()),
+            Some(escrow) if escrow.status != EscrowStatus::Held => Result::Err("Escrow has already been settled".to_string()),
+            Some(escrow) => {
+                escrow.status = status;
+                escrow.resolved_at = Some(time());
+                Result::Ok(escrow.clone())
+            }
         }
     });
-    
-    // Store transaction
+
+    if let Result::Ok(escrow) = &resolved {
+        let to = recipient(escrow);
+        WALLETS.with(|wallets| {
+            if let Some(wallet) = wallets.borrow_mut().get_mut(&to) {
+                wallet.balance += escrow.amount;
+            }
+        });
+    }
+    resolved
+}
+
+// Closes out the Purchase an escrow opened when it was created
+fn finish_escrow_transaction(escrow: &Escrow, status: TransactionStatus, memo: String) -> Result<Transaction, String> {
     TRANSACTIONS.with(|transactions| {
-        transactions.borrow_mut().insert(transaction_id, transaction.clone());
-    });
-    
-    Result::Ok(transaction)
-}
\ No newline at end of file
+        match transactions.borrow_mut().get_mut(&escrow.transaction_id) {
+            Some(stored) => {
+                stored.status = status;
+                stored.memo = Some(memo);
+                Result::Ok(stored.clone())
+            }
+            None => Result::Err("Transaction not found".to_string()),
+        }
+    })
+}
+
+// The buyer confirms the purchase; the seller is paid and the escrow's Purchase is completed
+#[update]
+fn release_escrow(escrow_id: u64) -> Result<Transaction, String> {
+    let caller = ic_cdk::caller();
+    let escrow = match resolve_escrow(escrow_id, EscrowStatus::Released, |escrow| escrow.buyer == caller, |escrow| escrow.seller) {
+        Result::Ok(escrow) => escrow,
+        Result::Err(e) => return Result::Err(e),
+    };
+    finish_escrow_transaction(&escrow, TransactionStatus::Completed, format!("Escrow {}", escrow_id))
+}
+
+// Returns held funds to the buyer; the seller or an admin can refund, the buyer cannot.
+// The escrow's Purchase is marked Failed so the history shows the money came back.
+#[update]
+fn refund_escrow(escrow_id: u64) -> Result<Escrow, String> {
+    let caller = ic_cdk::caller();
+    let caller_is_admin = is_admin(caller);
+    let escrow = match resolve_escrow(escrow_id, EscrowStatus::Refunded, |escrow| escrow.seller == caller || caller_is_admin, |escrow| escrow.buyer) {
+        Result::Ok(escrow) => escrow,
+        Result::Err(e) => return Result::Err(e),
+    };
+    if let Result::Err(e) = finish_escrow_transaction(&escrow, TransactionStatus::Failed, format!("Escrow {} refunded", escrow_id)) {
+        return Result::Err(e);
+    }
+    Result::Ok(escrow)
+}
+
+#[query]
+fn get_escrows() -> Vec<Escrow> {
+    let caller = ic_cdk::caller();
+    ESCROWS.with(|escrows| {
+        let mut mine: Vec<Escrow> = escrows.borrow().values()
+            .filter(|escrow| escrow.buyer == caller || escrow.seller == caller)
+            .cloned()
+            .collect();
+        mine.sort_by_key(|escrow| std::cmp::Reverse(escrow.created_at));
+        mine
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashtags_strip_trailing_punctuation_and_lowercase() {
+        assert_eq!(parse_hashtags("Learning #Rust, today"), vec!["#rust"]);
+        assert_eq!(parse_hashtags("I love #rust."), vec!["#rust"]);
+        assert_eq!(parse_hashtags("#Rust, #rust. #RUST!"), vec!["#rust"]);
+    }
+
+    #[test]
+    fn hashtags_support_unicode_letters() {
+        assert_eq!(parse_hashtags("Morning at the #Café"), vec!["#café"]);
+        assert_eq!(parse_hashtags("#日本語 and #über_cool"), vec!["#日本語", "#über_cool"]);
+    }
+
+    #[test]
+    fn hashtags_ignore_bare_hashes() {
+        assert!(parse_hashtags("####").is_empty());
+        assert!(parse_hashtags("# #! #.").is_empty());
+        assert_eq!(parse_hashtags("##rust"), vec!["#rust"]);
+    }
+
+    #[test]
+    fn hashtags_require_leading_hash() {
+        assert!(parse_hashtags("no tags here, just c#").is_empty());
+    }
+
+    #[test]
+    fn content_length_boundaries() {
+        let at_limit = "a".repeat(DEFAULT_MAX_POST_LENGTH);
+        let over_limit = "a".repeat(DEFAULT_MAX_POST_LENGTH + 1);
+        assert!(matches!(validate_content(&at_limit, DEFAULT_MAX_POST_LENGTH, "Post"), Result::Ok(())));
+        assert!(matches!(validate_content(&over_limit, DEFAULT_MAX_POST_LENGTH, "Post"), Result::Err(_)));
+        assert!(matches!(validate_content("x", MAX_COMMENT_LENGTH, "Comment"), Result::Ok(())));
+    }
+
+    #[test]
+    fn content_length_counts_chars_not_bytes() {
+        // Each emoji is 4 bytes in UTF-8 but a single scalar value
+        let emoji_at_limit = "😀".repeat(MAX_MESSAGE_LENGTH);
+        assert!(emoji_at_limit.len() > MAX_MESSAGE_LENGTH);
+        assert!(matches!(validate_content(&emoji_at_limit, MAX_MESSAGE_LENGTH, "Message"), Result::Ok(())));
+        let emoji_over_limit = "😀".repeat(MAX_MESSAGE_LENGTH + 1);
+        assert!(matches!(validate_content(&emoji_over_limit, MAX_MESSAGE_LENGTH, "Message"), Result::Err(_)));
+    }
+
+    #[test]
+    fn content_rejects_empty_and_whitespace() {
+        assert!(matches!(validate_content("", DEFAULT_MAX_POST_LENGTH, "Post"), Result::Err(_)));
+        assert!(matches!(validate_content(" \n\t ", DEFAULT_MAX_POST_LENGTH, "Post"), Result::Err(_)));
+    }
+
+    #[test]
+    fn comment_rate_limit_blocks_then_recovers() {
+        let user = Principal::from_slice(&[7]);
+        let start = 1_000 * 1_000_000_000;
+        for i in 0..MAX_COMMENTS_PER_WINDOW as u64 {
+            let now = start + i * 1_000_000_000;
+            assert!(matches!(check_rate_limit(&RECENT_COMMENTS, user, now, MAX_COMMENTS_PER_WINDOW, COMMENT_RATE_WINDOW_NS, "comments", "minute"), Result::Ok(())));
+            record_action(&RECENT_COMMENTS, user, now, MAX_COMMENTS_PER_WINDOW);
+        }
+
+        // 11th comment 10s after the first: the first one leaves the window in 50s
+        let too_soon = start + 10 * 1_000_000_000;
+        match check_rate_limit(&RECENT_COMMENTS, user, too_soon, MAX_COMMENTS_PER_WINDOW, COMMENT_RATE_WINDOW_NS, "comments", "minute") {
+            Result::Err(e) => assert!(e.contains("try again in 50 seconds"), "{}", e),
+            Result::Ok(()) => panic!("limit should have been hit"),
+        }
+
+        let recovered = start + COMMENT_RATE_WINDOW_NS;
+        assert!(matches!(check_rate_limit(&RECENT_COMMENTS, user, recovered, MAX_COMMENTS_PER_WINDOW, COMMENT_RATE_WINDOW_NS, "comments", "minute"), Result::Ok(())));
+    }
+
+    #[test]
+    fn message_rate_limit_is_per_user_and_slides() {
+        let spammer = Principal::from_slice(&[8]);
+        let bystander = Principal::from_slice(&[9]);
+        let now = 5_000 * 1_000_000_000;
+        for _ in 0..MAX_MESSAGES_PER_WINDOW {
+            record_action(&RECENT_MESSAGES, spammer, now, MAX_MESSAGES_PER_WINDOW);
+        }
+        assert!(rate_limit_wait(&RECENT_MESSAGES, spammer, now, MAX_MESSAGES_PER_WINDOW, MESSAGE_RATE_WINDOW_NS).is_some());
+        assert!(rate_limit_wait(&RECENT_MESSAGES, bystander, now, MAX_MESSAGES_PER_WINDOW, MESSAGE_RATE_WINDOW_NS).is_none());
+
+        // Once the window has passed, one new message is allowed and the buffer stays capped
+        let later = now + MESSAGE_RATE_WINDOW_NS;
+        assert!(rate_limit_wait(&RECENT_MESSAGES, spammer, later, MAX_MESSAGES_PER_WINDOW, MESSAGE_RATE_WINDOW_NS).is_none());
+        record_action(&RECENT_MESSAGES, spammer, later, MAX_MESSAGES_PER_WINDOW);
+        assert_eq!(RECENT_MESSAGES.with(|log| log.borrow()[&spammer].len()), MAX_MESSAGES_PER_WINDOW);
+        assert!(rate_limit_wait(&RECENT_MESSAGES, spammer, later, MAX_MESSAGES_PER_WINDOW, MESSAGE_RATE_WINDOW_NS).is_none());
+    }
+
+    fn test_post(id: u64, author: Principal, created_at: u64, content: &str, post_type: PostType) -> Post {
+        Post {
+            id,
+            author,
+            content: content.to_string(),
+            created_at,
+            likes: Vec::new(),
+            comments: Vec::new(),
+            hashtags: Vec::new(),
+            mentions: Vec::new(),
+            post_type,
+            reshare_count: 0,
+            media: Vec::new(),
+            visibility: Visibility::Public,
+            content_warning: None,
+            flagged: false,
+            comments_enabled: true,
+            hidden_by_reports: false,
+        }
+    }
+
+    fn reshare_of(original: &Post) -> PostType {
+        PostType::Reshare { original_post_id: original.id, original_author: original.author }
+    }
+
+    #[test]
+    fn two_resharers_of_one_original_appear_once() {
+        let author = Principal::from_slice(&[1]);
+        let first_resharer = Principal::from_slice(&[2]);
+        let second_resharer = Principal::from_slice(&[3]);
+        let original = test_post(1, author, 100, "original", PostType::Original);
+        let later_reshare = test_post(2, second_resharer, 300, "", reshare_of(&original));
+        let earlier_reshare = test_post(3, first_resharer, 200, "", reshare_of(&original));
+        let unrelated = test_post(4, author, 250, "other", PostType::Original);
+
+        let feed = dedup_reshares(vec![later_reshare, unrelated, earlier_reshare]);
+        let ids: Vec<u64> = feed.iter().map(|post| post.id).collect();
+        assert_eq!(ids, vec![4, 3]);
+        assert_eq!(feed[1].author, first_resharer);
+    }
+
+    #[test]
+    fn original_beats_its_reshares_but_quotes_are_kept() {
+        let author = Principal::from_slice(&[1]);
+        let resharer = Principal::from_slice(&[2]);
+        let original = test_post(1, author, 100, "original", PostType::Original);
+        let reshare = test_post(2, resharer, 200, "", reshare_of(&original));
+        let quote = test_post(3, resharer, 300, "my take", reshare_of(&original));
+
+        let ids: Vec<u64> = dedup_reshares(vec![reshare, quote, original]).iter().map(|post| post.id).collect();
+        assert_eq!(ids, vec![3, 1]);
+    }
+
+    #[test]
+    fn author_can_toggle_comments_on_their_post() {
+        let author = Principal::from_slice(&[1]);
+        let stranger = Principal::from_slice(&[2]);
+        POSTS.with(|posts| {
+            posts.borrow_mut().insert(1, test_post(1, author, 100, "hello", PostType::Original));
+        });
+        assert!(matches!(ensure_comments_open(1, stranger), Result::Ok(())));
+
+        assert!(matches!(set_comments_enabled_for(stranger, 1, false), Result::Err(_)));
+        assert!(matches!(ensure_comments_open(1, stranger), Result::Ok(())));
+
+        assert!(matches!(set_comments_enabled_for(author, 1, false), Result::Ok(post) if !post.comments_enabled));
+        assert!(matches!(ensure_comments_open(1, stranger), Result::Err(e) if e.contains("turned off comments")));
+
+        assert!(matches!(set_comments_enabled_for(author, 1, true), Result::Ok(post) if post.comments_enabled));
+        assert!(matches!(ensure_comments_open(1, stranger), Result::Ok(())));
+        assert!(matches!(ensure_comments_open(2, stranger), Result::Err(_)));
+    }
+
+    #[test]
+    fn close_friends_posts_only_take_comments_from_the_circle() {
+        let author = Principal::from_slice(&[1]);
+        let friend = Principal::from_slice(&[2]);
+        let stranger = Principal::from_slice(&[3]);
+        POSTS.with(|posts| {
+            posts.borrow_mut().insert(1, Post { visibility: Visibility::CloseFriends, ..test_post(1, author, 100, "inner circle", PostType::Original) });
+        });
+        CLOSE_FRIENDS.with(|close_friends| {
+            close_friends.borrow_mut().insert(author, vec![friend]);
+        });
+
+        assert!(matches!(ensure_comments_open(1, author), Result::Ok(())));
+        assert!(matches!(ensure_comments_open(1, friend), Result::Ok(())));
+        assert!(matches!(ensure_comments_open(1, stranger), Result::Err(e) if e == "Post not found"));
+    }
+
+    fn test_profile(id: Principal, username: &str) -> UserProfile {
+        UserProfile {
+            id,
+            username: username.to_string(),
+            bio: Vec::new(),
+            avatar_url: Vec::new(),
+            followers_count: 0,
+            following_count: 0,
+            created_at: 100,
+            active: true,
+            verified: false,
+            username_history: Vec::new(),
+            feed_preference: FeedAlgorithm::Personalized,
+            links: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn friends_of_friends_rank_above_strangers() {
+        let caller = Principal::from_slice(&[1]);
+        let friend = Principal::from_slice(&[2]);
+        let friend_of_friend = Principal::from_slice(&[3]);
+        let stranger = Principal::from_slice(&[4]);
+        PROFILES.with(|profiles| {
+            let mut profiles = profiles.borrow_mut();
+            for (id, name) in [(caller, "caller"), (friend, "friend"), (friend_of_friend, "fof"), (stranger, "stranger")] {
+                profiles.insert(id, test_profile(id, name));
+            }
+        });
+        FOLLOWS.with(|follows| {
+            let mut follows = follows.borrow_mut();
+            follows.insert(caller, vec![friend]);
+            follows.insert(friend, vec![friend_of_friend, caller]);
+        });
+
+        let suggested: Vec<Principal> = connection_suggestions(caller, 10, 1_000).iter().map(|profile| profile.id).collect();
+        assert_eq!(suggested, vec![friend_of_friend, stranger]);
+    }
+
+    #[test]
+    fn prolific_author_is_capped_per_page() {
+        let prolific = Principal::from_slice(&[1]);
+        let second = Principal::from_slice(&[2]);
+        let third = Principal::from_slice(&[3]);
+        // Already in score order: the prolific author holds the top four spots
+        let ranked = vec![
+            test_post(1, prolific, 100, "a", PostType::Original),
+            test_post(2, prolific, 100, "b", PostType::Original),
+            test_post(3, prolific, 100, "c", PostType::Original),
+            test_post(4, prolific, 100, "d", PostType::Original),
+            test_post(5, second, 100, "e", PostType::Original),
+            test_post(6, third, 100, "f", PostType::Original),
+        ];
+
+        let ids = |posts: Vec<Post>| posts.iter().map(|post| post.id).collect::<Vec<u64>>();
+        assert_eq!(ids(diversify_by_author(ranked.clone(), 2, 4)), vec![1, 2, 5, 6]);
+        // With nothing else left to show, capped posts backfill the page in their original order
+        assert_eq!(ids(diversify_by_author(ranked.clone(), 2, 6)), vec![1, 2, 5, 6, 3, 4]);
+        assert_eq!(ids(diversify_by_author(ranked, 1, 3)), vec![1, 5, 6]);
+    }
+
+    #[test]
+    fn unfollowing_while_deactivated_keeps_counts_consistent() {
+        let fan = Principal::from_slice(&[1]);
+        let quiet = Principal::from_slice(&[2]);
+        let set_active = |user: Principal, active: bool| {
+            PROFILES.with(|profiles| profiles.borrow_mut().get_mut(&user).unwrap().active = active);
+            adjust_follow_counts(user, active);
+        };
+        let counts = |user: Principal| PROFILES.with(|profiles| {
+            let profiles = profiles.borrow();
+            let profile = profiles.get(&user).unwrap();
+            (profile.followers_count, profile.following_count)
+        });
+        PROFILES.with(|profiles| {
+            let mut profiles = profiles.borrow_mut();
+            profiles.insert(fan, UserProfile { following_count: 1, ..test_profile(fan, "fan") });
+            profiles.insert(quiet, UserProfile { followers_count: 1, ..test_profile(quiet, "quiet") });
+        });
+        FOLLOWS.with(|follows| follows.borrow_mut().insert(fan, vec![quiet]));
+
+        set_active(quiet, false);
+        assert_eq!(counts(fan), (0, 0));
+        assert_eq!(counts(quiet), (1, 0));
+
+        assert!(matches!(unfollow_as(fan, quiet), Result::Ok(())));
+        set_active(quiet, true);
+        assert_eq!(counts(fan), (0, 0));
+        assert_eq!(counts(quiet), (0, 0));
+    }
+
+    #[test]
+    fn reshare_of_a_reshare_shows_the_original_content() {
+        let author = Principal::from_slice(&[1]);
+        let sharer = Principal::from_slice(&[2]);
+        let original = test_post(1, author, 100, "the original", PostType::Original);
+        let first = test_post(2, sharer, 110, "", reshare_of(&original));
+        let second = test_post(3, author, 120, "", reshare_of(&first));
+        POSTS.with(|posts| {
+            let mut posts = posts.borrow_mut();
+            posts.insert(1, original);
+            posts.insert(2, first);
+            posts.insert(3, second.clone());
+        });
+        assert_eq!(hydrate_reshare(second, sharer).content, "the original");
+    }
+
+    #[test]
+    fn reshares_of_report_hidden_posts_stay_empty_and_leave_feeds() {
+        let author = Principal::from_slice(&[1]);
+        let sharer = Principal::from_slice(&[2]);
+        let original = Post { hidden_by_reports: true, ..test_post(1, author, 100, "reported", PostType::Original) };
+        let reshare = test_post(2, sharer, 110, "", reshare_of(&original));
+        POSTS.with(|posts| {
+            let mut posts = posts.borrow_mut();
+            posts.insert(1, original);
+            posts.insert(2, reshare.clone());
+        });
+
+        assert!(!reshare_source_visible(&reshare, sharer));
+        assert_eq!(hydrate_reshare(reshare.clone(), sharer).content, "");
+        // The author still sees their own hidden post, so the reshare renders for them
+        assert_eq!(hydrate_reshare(reshare, author).content, "reported");
+    }
+
+    #[test]
+    fn tags_removed_by_an_edit_leave_the_topic_indexes() {
+        let author = Principal::from_slice(&[1]);
+        let tags = vec!["#rust".to_string(), "#wasm".to_string()];
+        TOPIC_AUTHORS.with(|authors| {
+            let mut authors = authors.borrow_mut();
+            for tag in &tags {
+                authors.insert(tag.clone(), HashSet::from([author]));
+            }
+        });
+        TRENDING_TOPICS.with(|topics| {
+            let mut topics = topics.borrow_mut();
+            for tag in &tags {
+                topics.insert(tag.clone(), TrendingTopic { hashtag: tag.clone(), count: 1, distinct_authors: 1, last_used: 100 });
+            }
+        });
+        TOPIC_USAGE.with(|usage| {
+            let mut usage = usage.borrow_mut();
+            for tag in &tags {
+                usage.insert(tag.clone(), VecDeque::from([(100, author)]));
+            }
+        });
+        update_hashtag_cooccurrence(&tags);
+
+        // The edited post now only carries #rust
+        POSTS.with(|posts| {
+            posts.borrow_mut().insert(1, Post { hashtags: vec!["#rust".to_string()], ..test_post(1, author, 100, "#rust", PostType::Original) });
+        });
+        remove_from_trending_topics(author, &["#wasm".to_string()], 100);
+        reduce_hashtag_cooccurrence(&tags);
+
+        assert!(TRENDING_TOPICS.with(|topics| !topics.borrow().contains_key("#wasm")));
+        assert!(TOPIC_USAGE.with(|usage| !usage.borrow().contains_key("#wasm")));
+        assert!(TOPIC_AUTHORS.with(|authors| !authors.borrow().contains_key("#wasm")));
+        assert!(HASHTAG_COOCCURRENCE.with(|cooccurrence| cooccurrence.borrow().is_empty()));
+        assert_eq!(TRENDING_TOPICS.with(|topics| topics.borrow().get("#rust").map(|topic| topic.count)), Some(1));
+    }
+
+    #[test]
+    fn following_activity_skips_posts_the_viewer_cannot_see() {
+        let viewer = Principal::from_slice(&[1]);
+        let friend = Principal::from_slice(&[2]);
+        let author = Principal::from_slice(&[3]);
+        POSTS.with(|posts| {
+            let mut posts = posts.borrow_mut();
+            posts.insert(1, test_post(1, author, 100, "public", PostType::Original));
+            posts.insert(2, Post { visibility: Visibility::CloseFriends, ..test_post(2, author, 100, "circle", PostType::Original) });
+        });
+        FOLLOWS.with(|follows| follows.borrow_mut().insert(viewer, vec![friend]));
+        ACTIVITY_LOG.with(|log| {
+            let mut log = log.borrow_mut();
+            for (id, post_id) in [(1, 1), (2, 2), (3, 99)] {
+                log.push_back(ActivityItem { id, actor: friend, action: ActivityAction::LikedPost { post_id }, created_at: 100 });
+            }
+        });
+
+        let ids: Vec<u64> = following_activity_for(viewer, 10).iter().map(|activity| activity.id).collect();
+        assert_eq!(ids, vec![1]);
+    }
+
+    fn test_message(id: u64, from: Principal, to: Principal, thread_id: &str) -> Message {
+        Message {
+            id,
+            from,
+            to,
+            content: "hi".to_string(),
+            created_at: 100,
+            read: false,
+            thread_id: thread_id.to_string(),
+            status: MessageStatus::Sent,
+            attachments: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn group_unread_counts_are_per_thread_and_per_member() {
+        let me = Principal::from_slice(&[1]);
+        let friend = Principal::from_slice(&[2]);
+        let other = Principal::from_slice(&[3]);
+        let dm_thread = get_thread_id(me, friend);
+        CHAT_THREADS.with(|threads| {
+            threads.borrow_mut().insert("group_1".to_string(), ChatThread {
+                id: "group_1".to_string(),
+                participants: vec![me, friend, other],
+                last_message: None,
+                updated_at: 100,
+                name: Some("Team".to_string()),
+                is_group: true,
+            });
+        });
+        MESSAGES.with(|messages| {
+            let mut messages = messages.borrow_mut();
+            messages.insert(1, test_message(1, friend, me, &dm_thread));
+            messages.insert(2, test_message(2, other, Principal::anonymous(), "group_1"));
+            messages.insert(3, test_message(3, me, Principal::anonymous(), "group_1"));
+            messages.insert(4, test_message(4, friend, Principal::anonymous(), "group_1"));
+        });
+
+        let unread = unread_counts_by_thread(me);
+        assert_eq!(unread.get(&dm_thread), Some(&1));
+        // The caller's own message doesn't count
+        assert_eq!(unread.get("group_1"), Some(&2));
+
+        assert_eq!(mark_group_read("group_1", me), 2);
+        assert_eq!(unread_counts_by_thread(me).get("group_1"), None);
+        // Other members keep their own read state
+        assert_eq!(unread_counts_by_thread(other).get("group_1"), Some(&2));
+    }
+
+    fn test_notification(id: u64, recipient: Principal, notification_type: NotificationType) -> Notification {
+        Notification { id, recipient, notification_type, created_at: 100, read: false }
+    }
+
+    #[test]
+    fn notifications_for_deleted_posts_are_pruned() {
+        let author = Principal::from_slice(&[1]);
+        let fan = Principal::from_slice(&[2]);
+        POSTS.with(|posts| {
+            let mut posts = posts.borrow_mut();
+            posts.insert(1, test_post(1, author, 100, "kept", PostType::Original));
+            posts.insert(2, test_post(2, author, 100, "deleted soon", PostType::Original));
+        });
+        NOTIFICATIONS.with(|notifications| {
+            let mut notifications = notifications.borrow_mut();
+            notifications.insert(1, test_notification(1, author, NotificationType::Like { post_id: 1, user_id: fan }));
+            notifications.insert(2, test_notification(2, author, NotificationType::Like { post_id: 2, user_id: fan }));
+            notifications.insert(3, test_notification(3, author, NotificationType::Reshare { post_id: 2, user_id: fan }));
+            notifications.insert(4, test_notification(4, author, NotificationType::Follow { user_id: fan }));
+        });
+        assert_eq!(remove_orphaned_notifications(), 0);
+
+        POSTS.with(|posts| posts.borrow_mut().remove(&2));
+        assert_eq!(remove_orphaned_notifications(), 2);
+
+        let mut remaining: Vec<u64> = NOTIFICATIONS.with(|notifications| notifications.borrow().keys().copied().collect());
+        remaining.sort();
+        assert_eq!(remaining, vec![1, 4]);
+    }
+
+    #[test]
+    fn comment_notifications_need_both_post_and_comment() {
+        let author = Principal::from_slice(&[1]);
+        let commenter = Principal::from_slice(&[2]);
+        POSTS.with(|posts| {
+            posts.borrow_mut().insert(1, test_post(1, author, 100, "post", PostType::Original));
+        });
+        NOTIFICATIONS.with(|notifications| {
+            notifications.borrow_mut().insert(1, test_notification(1, author, NotificationType::Comment { post_id: 1, user_id: commenter, comment_id: 9 }));
+        });
+
+        // Comment 9 was never stored, e.g. it was deleted along with its thread
+        assert_eq!(remove_orphaned_notifications(), 1);
+        assert!(NOTIFICATIONS.with(|notifications| notifications.borrow().is_empty()));
+    }
+
+    #[test]
+    fn deleting_a_reply_removes_its_reply_notification() {
+        let author = Principal::from_slice(&[1]);
+        let replier = Principal::from_slice(&[2]);
+        let comment = |id: u64, author: Principal, parent_id: Option<u64>, replies: Vec<u64>| Comment {
+            id,
+            post_id: 1,
+            author,
+            content: "comment".to_string(),
+            created_at: 100,
+            likes: Vec::new(),
+            parent_id,
+            replies,
+        };
+        POSTS.with(|posts| {
+            posts.borrow_mut().insert(1, Post { comments: vec![1, 2], ..test_post(1, author, 100, "post", PostType::Original) });
+        });
+        COMMENTS.with(|comments| {
+            let mut comments = comments.borrow_mut();
+            comments.insert(1, comment(1, author, None, vec![2]));
+            comments.insert(2, comment(2, replier, Some(1), Vec::new()));
+        });
+        NOTIFICATIONS.with(|notifications| {
+            notifications.borrow_mut().insert(1, test_notification(1, author, NotificationType::Reply { post_id: 1, comment_id: 2, user_id: replier }));
+        });
+
+        assert!(matches!(delete_comment_as(replier, 2), Result::Ok(())));
+        assert!(NOTIFICATIONS.with(|notifications| notifications.borrow().is_empty()));
+    }
+}
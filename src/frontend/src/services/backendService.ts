@@ -250,10 +250,10 @@ export const backendService = {
   /**
    * Searches for users by query string
    */
-  async searchUsers(query: string) {
+  async searchUsers(query: string, limit: number = 20, offset: number = 0, verifiedOnly: boolean = false) {
     console.log("Searching users with query:", query);
     try {
-      const result = await backend.search_users(query);
+      const result = await backend.search_users(query, BigInt(limit), BigInt(offset), verifiedOnly);
       console.log("Search users result:", result);
       return result;
     } catch (error) {
@@ -508,10 +508,10 @@ export const backendService = {
   /**
    * Search posts by hashtag
    */
-  async searchPostsByHashtag(hashtag: string) {
+  async searchPostsByHashtag(hashtag: string, limit: number = 50) {
     console.log("Searching posts by hashtag:", hashtag);
     try {
-      const result = await backend.search_posts_by_hashtag(hashtag);
+      const result = await backend.search_posts_by_hashtag(hashtag, BigInt(limit));
       console.log("Search posts by hashtag result:", result);
       return result;
     } catch (error) {
@@ -609,6 +609,10 @@ export const backendService = {
       const actor = await this.getAuthenticatedActor();
       const result = await actor.get_messages(Principal.fromText(withUserId));
       console.log("Get messages result:", result);
+      // Acknowledge delivery so the sender sees the double check; failures here shouldn't block the chat
+      actor.mark_messages_as_delivered(Principal.fromText(withUserId)).catch((error: any) => {
+        console.error("Error marking messages as delivered:", error);
+      });
       return result;
     } catch (error) {
       console.error("Error getting messages:", error);
@@ -623,7 +627,7 @@ export const backendService = {
     console.log("Getting chat threads");
     try {
       const actor = await this.getAuthenticatedActor();
-      const result = await actor.get_chat_threads();
+      const result = await actor.get_chat_threads({ All: null });
       console.log("Get chat threads result:", result);
       return result;
     } catch (error) {